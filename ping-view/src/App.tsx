@@ -7,8 +7,14 @@ import PingStats from "./PingStats";
 import { Pings } from "./Pings";
 import { MCServers } from "./MCServers";
 import { History } from "./History";
+import { Traces } from "./Traces";
+import { Distribution } from "./Distribution";
+import { Outages } from "./Outages";
+import { Report } from "./Report";
 
 export default function App() {
+  const [targets, setTargets] = React.useState<string[]>([]);
+  const [target, setTarget] = React.useState<string | null>(null);
   const [pings, setPings] = React.useState<api.PingData[]>([]);
   const [mcServers, setMcServers] = React.useState<api.MCServer[]>([]);
   const [hardware, setHardware] = React.useState<api.HardwareData>(
@@ -20,12 +26,14 @@ export default function App() {
     if (loading) return;
 
     loading = true;
-    let [p, m, h] = await Promise.all([
-      api.pings(new Date(), moment().subtract(1, "month").startOf("day").toDate(), 32 * 24 * 60),
+    let [t, p, m, h] = await Promise.all([
+      api.targets(),
+      api.pings(target, new Date(), moment().subtract(1, "month").startOf("day").toDate(), 32 * 24 * 60),
       api.mcServers(),
       api.hardware(),
     ]);
 
+    setTargets(t);
     setPings(p);
     setMcServers(m);
     setHardware(h);
@@ -34,25 +42,46 @@ export default function App() {
 
   React.useEffect(() => {
     reload();
-    let timer = setInterval(() => { reload(); }, 30000);
-    return () => clearInterval(timer);
     // eslint-disable-next-line
-  }, []);
+  }, [target]);
+
+  // Live updates instead of polling
+  const shown = target ?? targets[0] ?? null;
+  React.useEffect(() => {
+    if (shown === null) return;
+    const source = api.stream(shown, new Date(), {
+      ping: p => setPings(pings => [p, ...pings.filter(o => o.time < p.time)]),
+      hardware: setHardware,
+      mcServers: setMcServers,
+    });
+    return () => source.close();
+  }, [shown]);
 
   const until = moment().subtract(1, "hour").toDate();
   const untilIdx = pings.findIndex(p => p.time <= until);
-  const stats = api.stats(until, pings.slice(0, untilIdx).map(p => p.ping));
+  const stats = api.stats(until, pings.slice(0, untilIdx));
 
   return (
     <div className="App">
       <h1 style={{ textAlign: "center" }}>Ping Log</h1>
+      {targets.length > 1 &&
+        <div className="container" style={{ maxWidth: "28rem" }}>
+          <select className="form-select" value={target ?? targets[0]}
+            onChange={e => setTarget(e.target.value)}>
+            {targets.map(t => <option key={t} value={t}>{t}</option>)}
+          </select>
+        </div>}
       <div className="container" style={{ maxWidth: "28rem" }}>
         <PingStats {...stats} />
         <MCServers servers={mcServers} />
       </div>
       <div className="container">
         <Pings pings={pings} />
-        <History pings={pings} />
+        <History target={target} pings={pings} />
+        <Distribution target={target} />
+        <Outages target={target} />
+        <Report target={target} />
+        <Traces target={target} />
       </div>
       <div className="container" style={{ maxWidth: "28rem" }}>
         <Hardware {...hardware} />
@@ -0,0 +1,64 @@
+import * as React from 'react';
+import moment from 'moment';
+import { BarChart, Bar, CartesianGrid, XAxis, YAxis, Tooltip, ResponsiveContainer } from 'recharts';
+
+import api from './api';
+
+/** Number of histogram bins */
+const BINS = 30;
+
+export function Distribution({ target }: { target: string | null }) {
+    const [distribution, setDistribution] = React.useState<api.DistributionData | null>(null);
+
+    React.useEffect(() => {
+        const end = new Date();
+        const begin = moment(end).subtract(1, "day").toDate();
+        api.distribution(target, begin, end, BINS).then(setDistribution);
+    }, [target]);
+
+    if (distribution === null || distribution.p50 === null) return null;
+    const { histogram } = distribution;
+    const bins = histogram.counts.map((count, i) => ({
+        ping: (histogram.min + i * histogram.width).toPrecision(3),
+        count: count,
+    }));
+
+    return (
+        <div className="card m-5">
+            <div className="card-header">Distribution (24 hours)</div>
+            <div className="card-body">
+                <table className="full-width">
+                    <tbody>
+                        <tr>
+                            <td className="td-label text-secondary">p50</td>
+                            <td>{distribution.p50} ms</td>
+                            <td className="td-label text-secondary">p90</td>
+                            <td>{distribution.p90} ms</td>
+                            <td className="td-label text-secondary">p95</td>
+                            <td>{distribution.p95} ms</td>
+                            <td className="td-label text-secondary">p99</td>
+                            <td>{distribution.p99} ms</td>
+                        </tr>
+                    </tbody>
+                </table>
+                <ResponsiveContainer aspect={2.5} maxHeight={320}>
+                    <BarChart data={bins}>
+                        <CartesianGrid stroke="var(--bs-border-color)" />
+                        <XAxis dataKey="ping" stroke="var(--bs-body-color)" />
+                        <YAxis stroke="var(--bs-body-color)" />
+                        <Tooltip isAnimationActive={false} contentStyle={{
+                            width: "100px",
+                            backgroundColor: "var(--bs-secondary-bg)",
+                            border: "1px solid var(--bs-border-color)"
+                        }} />
+                        <Bar isAnimationActive={false} dataKey="count" fill="#4996fa" />
+                    </BarChart>
+                </ResponsiveContainer>
+                {(histogram.below > 0 || histogram.above > 0) &&
+                    <small className="text-secondary">
+                        {histogram.below} below {histogram.min} ms, {histogram.above} above {histogram.max} ms
+                    </small>}
+            </div>
+        </div>
+    );
+}
@@ -4,24 +4,40 @@ import { LineChart, Line, CartesianGrid, XAxis, YAxis, Tooltip, Legend, Responsi
 
 import api from './api';
 
-export function History({ pings }: { pings: api.PingData[] }) {
+export function History({ target, pings }: { target: string | null, pings: api.PingData[] }) {
     const [date, setDate] = React.useState(new Date());
+    const [history, setHistory] = React.useState<api.HistoryData[]>([]);
+    /** Begin of the hour that was still in progress when the history was fetched */
+    const [fetched, setFetched] = React.useState(moment().startOf("hour"));
 
     let minDate = moment(pings.at(-1)?.time).format("YYYY-MM-DD");
     let maxDate = moment().format("YYYY-MM-DD");
 
     const day = moment(date);
-    const begin = day.startOf("day").toDate();
-    const end = day.endOf("day").toDate();
     const str = day.format("YYYY-MM-DD");
 
-    let pingsBegin = pings.findIndex(p => p.time < end);
-    if (pingsBegin < 0) pingsBegin = 0;
-    let pingsEnd = pings.findIndex(p => p.time < begin);
-    const p = pings.slice(pingsBegin, pingsEnd);
+    React.useEffect(() => {
+        const begin = moment(date).startOf("day").toDate();
+        const end = moment(date).add(1, "day").startOf("day").toDate();
+        const hour = moment().startOf("hour");
+        api.history(target, begin, end).then(h => {
+            setHistory(h);
+            setFetched(hour);
+        });
+    }, [target, date]);
 
-    let history = api.statsArray(p);
-    history.reverse();
+    // The hours since the fetch are computed from the streamed pings instead of refetched
+    let data = history;
+    const live: api.HistoryData[] = [];
+    for (let hour = fetched.clone(); hour.isBefore(moment()); hour.add(1, "hour")) {
+        if (!hour.isSame(day, "day")) continue;
+        const begin = hour.toDate();
+        const end = hour.clone().add(1, "hour").toDate();
+        live.push(api.stats(begin, pings.filter(p => p.time >= begin && p.time < end)));
+    }
+    if (live.length > 0) {
+        data = [...history.filter(h => h.time < live[0].time), ...live];
+    }
 
     return (
         <div className="card m-5">
@@ -39,7 +55,7 @@ export function History({ pings }: { pings: api.PingData[] }) {
             </div>
             <div className="card-body">
                 <ResponsiveContainer aspect={2.5} maxHeight={320}>
-                    <LineChart data={history}>
+                    <LineChart data={data}>
                         <CartesianGrid stroke="var(--bs-border-color)" />
                         <XAxis dataKey={(element) => moment(element.time).format("LT")}
                             stroke="var(--bs-body-color)" />
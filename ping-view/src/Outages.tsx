@@ -0,0 +1,58 @@
+import * as React from 'react';
+import moment from 'moment';
+
+import api from './api';
+
+/** Days of outages that are shown */
+const DAYS = 30;
+
+function duration(seconds: number): string {
+    return moment.duration(seconds, "seconds").humanize();
+}
+
+export function Outages({ target }: { target: string | null }) {
+    const [outages, setOutages] = React.useState<api.OutageData[]>([]);
+    const [uptime, setUptime] = React.useState<api.UptimeData[]>([]);
+
+    React.useEffect(() => {
+        const end = new Date();
+        const begin = moment(end).subtract(DAYS, "days").toDate();
+        api.outages(target, begin, end).then(setOutages);
+        api.uptime().then(setUptime);
+    }, [target]);
+
+    // Without a selection, the first target is shown
+    const current = target === null ? uptime[0] : uptime.find(u => u.target === target);
+    if (current === undefined) return null;
+    return (
+        <div className="card m-5">
+            <div className="card-header">Uptime</div>
+            <div className="card-body">
+                <table className="full-width">
+                    <tbody>
+                        <tr>
+                            <td className="td-label text-secondary">24 hours</td>
+                            <td>{current.day.toFixed(3)} %</td>
+                            <td className="td-label text-secondary">7 days</td>
+                            <td>{current.week.toFixed(3)} %</td>
+                            <td className="td-label text-secondary">30 days</td>
+                            <td>{current.month.toFixed(3)} %</td>
+                        </tr>
+                    </tbody>
+                </table>
+                {outages.length > 0 &&
+                    <table className="full-width mt-3">
+                        <tbody>
+                            {outages.map(o => (
+                                <tr key={o.start.getTime()}>
+                                    <td>{moment(o.start).format("L LT")}</td>
+                                    <td>{o.end === null ? <span className="text-danger">ongoing</span> : duration(o.duration)}</td>
+                                    <td className="text-secondary">{o.lost} lost</td>
+                                </tr>
+                            ))}
+                        </tbody>
+                    </table>}
+            </div>
+        </div>
+    );
+}
@@ -11,8 +11,8 @@ export function Pings({ pings }: { pings: api.PingData[] }) {
     p.reverse();
     const data = p.map((e) => {
         return {
-            ping: e.ping < 1000 ? e.ping : undefined,
-            lost: e.ping < 1000 ? undefined : 1,
+            ping: e.lost ? undefined : e.ping,
+            lost: e.lost ? 1 : undefined,
             time: e.time,
         }
     });
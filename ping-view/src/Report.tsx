@@ -0,0 +1,71 @@
+import * as React from 'react';
+import moment from 'moment';
+
+import api from './api';
+
+function percent(value: number | null): string {
+    return value === null ? "-" : value.toFixed(3) + " %";
+}
+
+function duration(seconds: number | null): string {
+    return seconds === null ? "-" : moment.duration(seconds, "seconds").humanize();
+}
+
+export function Report({ target }: { target: string | null }) {
+    const [from, setFrom] = React.useState(moment().subtract(2, "months").format("YYYY-MM"));
+    const [to, setTo] = React.useState(moment().format("YYYY-MM"));
+    const [reports, setReports] = React.useState<api.ReportData[]>([]);
+
+    React.useEffect(() => {
+        api.report(from, to).then(setReports);
+    }, [from, to]);
+
+    // Without a selection, the first target is shown
+    const report = target === null ? reports[0] : reports.find(r => r.target === target);
+    return (
+        <div className="card m-5">
+            <div className="card-header">
+                <div className="row align-items-center">
+                    <div className="col">
+                        <span>Availability</span>
+                    </div>
+                    <div className="col col-auto">
+                        <input type="month" value={from} max={to} onChange={e => setFrom(e.target.value)} />
+                        {" – "}
+                        <input type="month" value={to} min={from} max={moment().format("YYYY-MM")}
+                            onChange={e => setTo(e.target.value)} />
+                    </div>
+                </div>
+            </div>
+            <div className="card-body">
+                {report === undefined ? <span className="text-secondary">No report</span> :
+                    <table className="full-width">
+                        <thead>
+                            <tr className="text-secondary">
+                                <th>Month</th>
+                                <th>Availability</th>
+                                <th>Outages</th>
+                                <th>Downtime</th>
+                                <th>MTTR</th>
+                                <th>Over {report.budget.latency} ms</th>
+                            </tr>
+                        </thead>
+                        <tbody>
+                            {report.months.map(m => (
+                                <tr key={m.month}>
+                                    <td>{m.month}</td>
+                                    <td className={m.availability_met === false ? "text-danger" : ""}>
+                                        {percent(m.availability)}
+                                    </td>
+                                    <td>{m.outages}</td>
+                                    <td>{m.downtime > 0 ? duration(m.downtime) : "-"}</td>
+                                    <td>{duration(m.mttr)}</td>
+                                    <td>{percent(m.over_budget)}</td>
+                                </tr>
+                            ))}
+                        </tbody>
+                    </table>}
+            </div>
+        </div>
+    );
+}
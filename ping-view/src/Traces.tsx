@@ -0,0 +1,42 @@
+import * as React from 'react';
+import moment from 'moment';
+
+import api from './api';
+
+/** Number of traces that are shown */
+const COUNT = 5;
+
+export function Traces({ target }: { target: string | null }) {
+    const [traces, setTraces] = React.useState<api.TraceData[]>([]);
+
+    React.useEffect(() => {
+        api.traces(target, COUNT).then(setTraces);
+    }, [target]);
+
+    if (traces.length === 0) return null;
+    return (
+        <div className="card m-5">
+            <div className="card-header">Traces</div>
+            <div className="card-body">
+                {traces.map(t => (
+                    <details key={t.time.getTime()}>
+                        <summary>
+                            {moment(t.time).format("L LT")} <span className="text-secondary">({t.reason})</span> {t.destination}
+                        </summary>
+                        <table className="full-width">
+                            <tbody>
+                                {t.hops.map(h => (
+                                    <tr key={h.ttl}>
+                                        <td className="td-label text-secondary">{h.ttl}</td>
+                                        <td>{h.addr ?? "*"}</td>
+                                        <td>{h.rtts.map(r => r.toPrecision(3)).join(" / ")} ms</td>
+                                    </tr>
+                                ))}
+                            </tbody>
+                        </table>
+                    </details>
+                ))}
+            </div>
+        </div>
+    );
+}
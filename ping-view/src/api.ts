@@ -2,8 +2,16 @@ import moment from "moment";
 
 namespace api {
     const API_LOG = "/api/pings";
+    const API_TARGETS = "/api/targets";
+    const API_TRACES = "/api/traces";
+    const API_STATS = "/api/stats";
+    const API_DISTRIBUTION = "/api/distribution";
     const API_HW = "/api/hw";
     const API_MC = "/api/mc";
+    const API_STREAM = "/api/stream";
+    const API_OUTAGES = "/api/outages";
+    const API_UPTIME = "/api/uptime";
+    const API_REPORT = "/api/report";
 
     export interface HistoryData {
         time: Date,
@@ -19,6 +27,47 @@ namespace api {
         time: Date,
         /** Response time in milliseconds. */
         ping: number,
+        /** Result of the request (e.g. "success", "timeout", "unreachable"). */
+        outcome: string,
+        /** Whether the request failed. */
+        lost: boolean,
+        /** Fraction of lost echo requests if multiple were sent per interval. */
+        loss: number,
+    }
+
+    export interface Hop {
+        /** Distance to the router. */
+        ttl: number,
+        /** Address of the router, null if it did not respond. */
+        addr: string | null,
+        /** Response times in milliseconds. */
+        rtts: number[],
+    }
+
+    export interface TraceData {
+        time: Date,
+        /** Cause of the trace ("loss" or "latency"). */
+        reason: string,
+        destination: string,
+        hops: Hop[],
+    }
+
+    export interface DistributionData {
+        count: number,
+        loss: number,
+        /** Response time percentiles, null without replies. */
+        p50: number | null,
+        p90: number | null,
+        p95: number | null,
+        p99: number | null,
+        histogram: {
+            min: number,
+            max: number,
+            width: number,
+            counts: number[],
+            below: number,
+            above: number,
+        },
     }
 
     export interface HardwareData {
@@ -40,6 +89,51 @@ namespace api {
         max_players: number,
     }
 
+    export interface OutageData {
+        target: string,
+        /** Time of the first lost ping. */
+        start: Date,
+        /** Time of the first successful ping afterwards, null while ongoing. */
+        end: Date | null,
+        /** Length in seconds. */
+        duration: number,
+        /** Number of lost pings. */
+        lost: number,
+    }
+
+    export interface UptimeData {
+        target: string,
+        /** Uptime in percent over the last 24 hours. */
+        day: number,
+        /** Uptime in percent over the last 7 days. */
+        week: number,
+        /** Uptime in percent over the last 30 days. */
+        month: number,
+    }
+
+    export interface MonthReport {
+        /** Calendar month like "2026-09". */
+        month: string,
+        samples: number,
+        lost: number,
+        /** Time outside of outages in percent, null without samples. */
+        availability: number | null,
+        availability_met: boolean | null,
+        outages: number,
+        /** Total length of the outages in seconds. */
+        downtime: number,
+        /** Mean time to repair in seconds. */
+        mttr: number | null,
+        /** Successful pings over the latency budget in percent. */
+        over_budget: number | null,
+    }
+
+    export interface ReportData {
+        target: string,
+        budget: { availability: number, latency: number },
+        months: MonthReport[],
+    }
+
     function get<T>(obj: any, prop: string, def: T): T {
         if (obj instanceof Object && prop in obj && obj[prop] as T)
             return obj[prop];
@@ -47,20 +141,150 @@ namespace api {
     }
 
     /** Fetch the most recent pings (latest first) */
-    export async function pings(start: Date, end: Date, count: number): Promise<PingData[]> {
-        const response = await fetch(encodeURI(API_LOG + "?" + new URLSearchParams({
+    export async function pings(target: string | null, start: Date, end: Date, count: number): Promise<PingData[]> {
+        let params = new URLSearchParams({
             start: Math.round(start.getTime() / 1000.0).toString(),
             end: Math.round(end.getTime() / 1000.0).toString(),
             count: count.toString(),
-        }).toString()));
+        });
+        if (target !== null) params.set("target", target);
+        const response = await fetch(API_LOG + "?" + params.toString());
+        if (!response.ok) return [];
 
         const parsed: any[] = await response.json();
-        return parsed.map(p => {
-            return {
-                time: new Date(get<number>(p, "time", 0) * 1000.0),
-                ping: get<number>(p, "ping", 0.0),
-            }
+        return parsed.map(parsePing);
+    }
+
+    function parsePing(p: any): PingData {
+        const outcome = get<string>(p, "outcome", "success");
+        return {
+            time: new Date(get<number>(p, "time", 0) * 1000.0),
+            ping: get<number>(p, "ping", 0.0),
+            outcome: outcome,
+            lost: outcome !== "success",
+            loss: p.burst ? 1.0 - p.burst.received / p.burst.sent : (outcome !== "success" ? 1.0 : 0.0),
+        }
+    }
+
+    export interface StreamHandlers {
+        ping: (ping: PingData) => void,
+        hardware: (hardware: HardwareData) => void,
+        mcServers: (servers: MCServer[]) => void,
+    }
+
+    /**
+     * Subscribe to the live updates of the target, starting after `since`.
+     * The browser reconnects automatically without missing pings.
+     */
+    export function stream(target: string | null, since: Date | null, handlers: StreamHandlers): EventSource {
+        let params = new URLSearchParams();
+        if (target !== null) params.set("target", target);
+        if (since !== null) params.set("since", Math.round(since.getTime() / 1000.0).toString());
+        const source = new EventSource(API_STREAM + "?" + params.toString());
+        source.addEventListener("ping", e => handlers.ping(parsePing(JSON.parse(e.data))));
+        source.addEventListener("hw", e => handlers.hardware(JSON.parse(e.data)));
+        source.addEventListener("mc", e => handlers.mcServers(JSON.parse(e.data)));
+        return source;
+    }
+
+    /** Fetch the path traces of the target (latest first). */
+    export async function traces(target: string | null, count: number): Promise<TraceData[]> {
+        let params = new URLSearchParams({ count: count.toString() });
+        if (target !== null) params.set("target", target);
+        const response = await fetch(API_TRACES + "?" + params.toString());
+        if (!response.ok) return [];
+
+        const parsed: any[] = await response.json();
+        return parsed.map(t => ({
+            time: new Date(get<number>(t, "time", 0) * 1000.0),
+            reason: get<string>(t, "reason", "loss"),
+            destination: get<string>(t, "destination", ""),
+            hops: get<Hop[]>(t, "hops", []),
+        }));
+    }
+
+    /** Fetch the hourly statistics between begin and end (oldest first). */
+    export async function history(target: string | null, begin: Date, end: Date): Promise<HistoryData[]> {
+        let params = new URLSearchParams({
+            start: Math.round(begin.getTime() / 1000.0).toString(),
+            end: Math.round(end.getTime() / 1000.0).toString(),
+            bucket: "3600",
+            // Align the hours to the time zone of the viewer (with its DST changes)
+            tz: Intl.DateTimeFormat().resolvedOptions().timeZone ?? moment(begin).format("Z"),
         });
+        if (target !== null) params.set("target", target);
+        const response = await fetch(API_STATS + "?" + params.toString());
+        if (!response.ok) return [];
+
+        const parsed: any[] = await response.json();
+        return parsed.map(b => ({
+            time: new Date(get<number>(b, "time", 0) * 1000.0),
+            min: get<number>(b, "min", 0.0),
+            max: get<number>(b, "max", 0.0),
+            avg: get<number>(b, "avg", 0.0),
+            lost: get<number>(b, "loss", 0.0),
+            count: get<number>(b, "count", 0),
+        }));
+    }
+
+    /** Fetch the response time percentiles and histogram between begin and end. */
+    export async function distribution(target: string | null, begin: Date, end: Date, bins: number, min?: number, max?: number): Promise<DistributionData | null> {
+        let params = new URLSearchParams({
+            start: Math.round(begin.getTime() / 1000.0).toString(),
+            end: Math.round(end.getTime() / 1000.0).toString(),
+            bins: bins.toString(),
+        });
+        if (target !== null) params.set("target", target);
+        if (min !== undefined) params.set("min", min.toString());
+        if (max !== undefined) params.set("max", max.toString());
+        const response = await fetch(API_DISTRIBUTION + "?" + params.toString());
+        if (!response.ok) return null;
+        return await response.json();
+    }
+
+    /** Fetch the outages of the target between begin and end (latest first). */
+    export async function outages(target: string | null, begin: Date, end: Date): Promise<OutageData[]> {
+        let params = new URLSearchParams({
+            start: Math.round(begin.getTime() / 1000.0).toString(),
+            end: Math.round(end.getTime() / 1000.0).toString(),
+        });
+        if (target !== null) params.set("target", target);
+        const response = await fetch(API_OUTAGES + "?" + params.toString());
+        if (!response.ok) return [];
+
+        const parsed: any[] = await response.json();
+        return parsed.map(o => ({
+            target: get<string>(o, "target", ""),
+            start: new Date(get<number>(o, "start", 0) * 1000.0),
+            end: o.end != null ? new Date(o.end * 1000.0) : null,
+            duration: get<number>(o, "duration", 0),
+            lost: get<number>(o, "lost", 0),
+        }));
+    }
+
+    /** Fetch the uptime of all targets. */
+    export async function uptime(): Promise<UptimeData[]> {
+        const response = await fetch(API_UPTIME);
+        if (!response.ok) return [];
+        return await response.json();
+    }
+
+    /** Fetch the monthly availability reports of all targets ("yyyy-mm"). */
+    export async function report(from: string, to: string): Promise<ReportData[]> {
+        let params = new URLSearchParams({
+            from: from,
+            to: to,
+            tz: Intl.DateTimeFormat().resolvedOptions().timeZone ?? moment().format("Z"),
+        });
+        const response = await fetch(API_REPORT + "?" + params.toString());
+        if (!response.ok) return [];
+        return await response.json();
+    }
+
+    /** Fetch the configured ping targets. */
+    export async function targets(): Promise<string[]> {
+        const response = await fetch(API_TARGETS);
+        return await response.json();
     }
 
     /** Fetch the hardware statistics. */
@@ -76,77 +300,40 @@ namespace api {
     }
 
     /** Compute the combined statistic for all pings before the given time. */
-    export function stats(time: Date, pings: number[]): HistoryData {
-        let min = 1000.0;
+    export function stats(time: Date, pings: PingData[]): HistoryData {
+        let min = Infinity;
         let max = 0.0;
         let sum = 0.0;
         let lost = 0.0;
+        let replies = 0;
         let count = pings.length;
 
-        for (const ping of pings) {
-            if (ping < min) {
-                min = ping;
-            }
-            if (ping >= 1000.0) {
-                lost += 1.0;
-            } else {
+        for (const { ping, lost: isLost, loss } of pings) {
+            lost += loss;
+            if (!isLost) {
+                if (ping < min) {
+                    min = ping;
+                }
                 if (ping > max) {
                     max = ping;
                 }
                 sum += ping;
+                replies += 1;
             }
         }
 
-        let avg = Math.round(1000.0 * sum / (count - lost)) / 1000.0;
+        let avg = Math.round(1000.0 * sum / replies) / 1000.0;
         lost = Math.round(1000.0 * lost / count) / 1000.0;
 
         return {
             time: time,
-            min: min >= 1000.0 ? 0.0 : min,
+            min: min === Infinity ? 0.0 : min,
             max: max <= 0.0 ? 0.0 : max,
             avg: isNaN(avg) ? 0.0 : avg,
             lost: isNaN(lost) ? 0.0 : lost,
             count: count,
         };
     }
-
-    function group<T, K, V>(arr: T[], f: (e: T) => [K, V]): [K, V[]][] {
-        let out: [K, V[]][] = [];
-        for (const e of arr) {
-            const [k, v] = f(e);
-            let last = out.at(-1);
-            if (last !== undefined && last[0] === k) {
-                last[1].push(v);
-            } else {
-                out.push([k, [v]]);
-            }
-
-        }
-        return out;
-    }
-
-    export function statsArray(pings: PingData[]): HistoryData[] {
-        let chunks = group(pings, p => [moment(p.time).startOf("hour").toDate().getTime(), p.ping]);
-        let out: HistoryData[] = []
-
-        if (chunks.length > 0) {
-            let i = 0;
-            let time = new Date(chunks[0][0]);
-            // Fill non-existing hours with 0
-            while (i < chunks.length) {
-                let [t, chunk] = chunks[i];
-                if (t >= time.getTime()) {
-                    out.push(stats(time, chunk));
-                    i += 1;
-                } else {
-                    out.push({ time: time, min: 0, max: 0, avg: 0, lost: 0, count: 0 });
-                }
-                time = moment(time).subtract(1, "hour").toDate();
-            }
-        }
-
-        return out;
-    }
 }
 
 export default api;
@@ -0,0 +1,467 @@
+//! Threshold alerts on the pings and the system and server status.
+//!
+//! A rule like `loss>5,2/10m@1.1.1.1` fires if the loss of the target over the
+//! last ten minutes exceeds 5% and resolves once it falls to 2% or below. The gap
+//! between the two thresholds keeps the alerts from flapping.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+use super::ping::{Outcome, Ping};
+use super::ping_aggregate::percentile;
+use super::stream::{Hub, Update};
+
+/// Number of resolved alerts that are kept
+const MAX_RECENT: usize = 100;
+/// Default window of the rules in seconds
+const DEFAULT_WINDOW: i64 = 5 * 60;
+/// Default gap between the firing and resolving threshold
+const DEFAULT_HYSTERESIS: f64 = 0.1;
+
+/// Measured value of a rule
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    /// Lost requests of a target in percent
+    Loss,
+    /// Average response time of a target in ms
+    Avg,
+    /// Response time percentile of a target in ms
+    Percentile(f64),
+    /// CPU temperature in °C
+    Temperature,
+    /// CPU load in percent
+    Load,
+    /// Failed status requests of a Minecraft server in percent
+    Offline,
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Metric::Loss => write!(f, "loss"),
+            Metric::Avg => write!(f, "avg"),
+            Metric::Percentile(p) => write!(f, "p{p}"),
+            Metric::Temperature => write!(f, "temp"),
+            Metric::Load => write!(f, "load"),
+            Metric::Offline => write!(f, "offline"),
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "loss" => Metric::Loss,
+            "avg" => Metric::Avg,
+            "temp" => Metric::Temperature,
+            "load" => Metric::Load,
+            "offline" => Metric::Offline,
+            _ => match s.strip_prefix('p').and_then(|p| p.parse().ok()) {
+                Some(p) if (0.0..=100.0).contains(&p) => Metric::Percentile(p),
+                _ => return Err(format!("unknown metric '{s}'")),
+            },
+        })
+    }
+}
+
+/// Condition of an alert
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub metric: Metric,
+    /// The alert fires above this value
+    pub fire: f64,
+    /// The alert resolves at or below this value
+    pub resolve: f64,
+    /// Duration of the evaluated samples in seconds
+    pub window: i64,
+    /// Target or server the rule is limited to
+    pub subject: Option<String>,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}>{},{}", self.metric, self.fire, self.resolve)?;
+        write!(f, "/{}s", self.window)?;
+        if let Some(subject) = &self.subject {
+            write!(f, "@{subject}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    /// Parses `<metric>><fire>[,<resolve>][/<window>][@<subject>]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (rule, subject) = match s.split_once('@') {
+            Some((rule, subject)) => (rule, Some(subject.to_owned())),
+            None => (s, None),
+        };
+        let (rule, window) = match rule.split_once('/') {
+            Some((rule, window)) => (rule, parse_duration(window)?),
+            None => (rule, DEFAULT_WINDOW),
+        };
+        let (metric, thresholds) = rule
+            .split_once('>')
+            .ok_or_else(|| format!("missing threshold in '{s}'"))?;
+        let (fire, resolve) = match thresholds.split_once(',') {
+            Some((fire, resolve)) => (parse_value(fire)?, parse_value(resolve)?),
+            None => {
+                let fire = parse_value(thresholds)?;
+                (fire, fire * (1.0 - DEFAULT_HYSTERESIS))
+            }
+        };
+        if resolve > fire {
+            return Err(format!("resolve threshold above the firing one in '{s}'"));
+        }
+        Ok(Rule {
+            metric: metric.trim().parse()?,
+            fire,
+            resolve,
+            window,
+            subject,
+        })
+    }
+}
+
+/// Parses a threshold with an optional unit (`5%`, `100ms`, `70C`)
+fn parse_value(s: &str) -> Result<f64, String> {
+    let value = s
+        .trim()
+        .trim_end_matches(['%', 'C', '°'])
+        .trim_end_matches("ms");
+    value
+        .parse()
+        .map_err(|_| format!("invalid threshold '{s}'"))
+}
+
+/// Parses a duration like `90s`, `10m` or `1h` (seconds by default)
+fn parse_duration(s: &str) -> Result<i64, String> {
+    let s = s.trim();
+    let (value, unit) = match s.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        _ => return Err(format!("invalid duration '{s}'")),
+    };
+    value
+        .parse::<i64>()
+        .map(|v| v * unit)
+        .map_err(|_| format!("invalid duration '{s}'"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Firing,
+    Resolved,
+}
+
+/// Alert of a rule for a target, server or the system
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alert {
+    pub rule: String,
+    pub subject: String,
+    pub state: State,
+    /// Value that fired or resolved the alert
+    pub value: f64,
+    /// Time the alert fired
+    pub since: i64,
+    /// Time the alert resolved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until: Option<i64>,
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self.state {
+            State::Firing => "firing",
+            State::Resolved => "resolved",
+        };
+        write!(
+            f,
+            "{} of {} {state} ({:.1})",
+            self.rule, self.subject, self.value
+        )
+    }
+}
+
+/// Active and recently resolved alerts
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Alerts {
+    pub active: Vec<Alert>,
+    /// Newest first
+    pub recent: Vec<Alert>,
+}
+
+/// Times and values of the samples in the window of a rule
+type Samples = VecDeque<(i64, Option<f64>)>;
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Samples of each rule and subject
+    samples: HashMap<(usize, String), Samples>,
+    active: BTreeMap<(usize, String), Alert>,
+    recent: VecDeque<Alert>,
+}
+
+/// Evaluates the rules on the incoming updates
+#[derive(Debug)]
+pub struct Engine {
+    rules: Vec<Rule>,
+    inner: Mutex<Inner>,
+}
+
+impl Engine {
+    pub fn new(rules: Vec<Rule>) -> Engine {
+        Engine {
+            rules,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn alerts(&self) -> Alerts {
+        let inner = self.inner.lock().unwrap();
+        Alerts {
+            active: inner.active.values().cloned().collect(),
+            recent: inner.recent.iter().cloned().collect(),
+        }
+    }
+
+    /// Adds the samples of the update and returns the alerts that fired or
+    /// resolved
+    pub fn update(&self, time: i64, update: &Update) -> Vec<Alert> {
+        let mut changes = Vec::new();
+        let mut inner = self.inner.lock().unwrap();
+        for (i, rule) in self.rules.iter().enumerate() {
+            for (subject, sample) in samples(rule.metric, update) {
+                if rule.subject.as_ref().is_some_and(|s| *s != subject) {
+                    continue;
+                }
+                let key = (i, subject);
+                let samples = inner.samples.entry(key.clone()).or_default();
+                samples.push_back((time, sample));
+                while samples.len() > 1 && samples[0].0 <= time - rule.window {
+                    samples.pop_front();
+                }
+                let Some(value) = evaluate(rule.metric, samples) else {
+                    continue;
+                };
+
+                if let Some(alert) = inner.active.get(&key) {
+                    if value <= rule.resolve {
+                        let alert = Alert {
+                            state: State::Resolved,
+                            value,
+                            until: Some(time),
+                            ..alert.clone()
+                        };
+                        inner.active.remove(&key);
+                        inner.recent.push_front(alert.clone());
+                        inner.recent.truncate(MAX_RECENT);
+                        changes.push(alert);
+                    }
+                } else if value > rule.fire {
+                    let alert = Alert {
+                        rule: rule.to_string(),
+                        subject: key.1.clone(),
+                        state: State::Firing,
+                        value,
+                        since: time,
+                        until: None,
+                    };
+                    inner.active.insert(key, alert.clone());
+                    changes.push(alert);
+                }
+            }
+        }
+        changes
+    }
+}
+
+/// Returns the samples of the metric by subject, `None` for missing values
+/// (e.g. the response time of a lost ping)
+fn samples(metric: Metric, update: &Update) -> Vec<(String, Option<f64>)> {
+    match (metric, update) {
+        (Metric::Loss, Update::Ping(target, ping)) => {
+            vec![(target.clone(), Some(ping.loss() * 100.0))]
+        }
+        (Metric::Avg | Metric::Percentile(_), Update::Ping(target, ping)) => {
+            vec![(target.clone(), rtt(ping))]
+        }
+        (Metric::Temperature, Update::Hw(status)) => {
+            vec![("system".into(), Some(status.temperature.into()))]
+        }
+        (Metric::Load, Update::Hw(status)) => vec![("system".into(), Some(status.load.into()))],
+        (Metric::Offline, Update::Mc(servers)) => servers
+            .iter()
+            .map(|s| (s.addr.clone(), Some(if s.online() { 0.0 } else { 100.0 })))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn rtt(ping: &Ping) -> Option<f64> {
+    (ping.outcome == Outcome::Success).then_some(ping.ping)
+}
+
+/// Returns the value of the metric over the samples
+fn evaluate(metric: Metric, samples: &Samples) -> Option<f64> {
+    let mut values: Vec<f64> = samples.iter().filter_map(|(_, v)| *v).collect();
+    match metric {
+        Metric::Percentile(p) => {
+            values.sort_unstable_by(f64::total_cmp);
+            percentile(&values, p)
+        }
+        _ if values.is_empty() => None,
+        _ => Some(values.iter().sum::<f64>() / values.len() as f64),
+    }
+}
+
+/// Evaluates the rules on the updates of the hub and publishes the alerts
+pub async fn run(engine: Arc<Engine>, hub: Arc<Hub>) {
+    let mut receiver = hub.receiver();
+    loop {
+        let update = match receiver.recv().await {
+            Ok(Update::Alert(_) | Update::Outage(_)) => continue,
+            Ok(update) => update,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("alerts skipped {skipped} updates");
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        let time = match &update {
+            Update::Ping(_, ping) => ping.time,
+            _ => chrono::Local::now().timestamp(),
+        };
+        for alert in engine.update(time, &update) {
+            info!("alert {alert}");
+            hub.publish(Update::Alert(alert));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::{hw, mc};
+
+    #[test]
+    fn rules() {
+        let rule: Rule = "loss>5%,2%/10m@1.1.1.1".parse().unwrap();
+        assert_eq!(
+            rule,
+            Rule {
+                metric: Metric::Loss,
+                fire: 5.0,
+                resolve: 2.0,
+                window: 600,
+                subject: Some("1.1.1.1".into()),
+            }
+        );
+        assert_eq!(rule.to_string(), "loss>5,2/600s@1.1.1.1");
+        let rule: Rule = "p95>100ms".parse().unwrap();
+        assert_eq!(rule.metric, Metric::Percentile(95.0));
+        assert_eq!((rule.resolve, rule.window), (90.0, DEFAULT_WINDOW));
+        let rule: Rule = "temp>70C/90".parse().unwrap();
+        assert_eq!(
+            (rule.metric, rule.fire, rule.window),
+            (Metric::Temperature, 70.0, 90)
+        );
+
+        assert!("loss".parse::<Rule>().is_err());
+        assert!("jitter>5".parse::<Rule>().is_err());
+        assert!("loss>5,10".parse::<Rule>().is_err());
+        assert!("loss>5/10d".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn hysteresis() {
+        let engine = Engine::new(vec!["loss>40,10/300".parse().unwrap()]);
+        let ping = |time, lost| {
+            let ping = match lost {
+                true => Ping::lost(time, Outcome::Timeout),
+                false => Ping::new(time, 10.0),
+            };
+            Update::Ping("a".into(), ping)
+        };
+
+        assert!(engine.update(0, &ping(0, false)).is_empty());
+        // 50% loss
+        let changes = engine.update(60, &ping(60, true));
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].state, changes[0].value), (State::Firing, 50.0));
+        // Still firing below the firing threshold
+        for time in [120, 180, 240, 300] {
+            assert!(engine.update(time, &ping(time, false)).is_empty());
+        }
+        assert_eq!(engine.alerts().active.len(), 1);
+        // The lost ping has left the window
+        let changes = engine.update(360, &ping(360, false));
+        assert_eq!(changes[0].state, State::Resolved);
+        assert_eq!((changes[0].since, changes[0].until), (60, Some(360)));
+
+        let alerts = engine.alerts();
+        assert!(alerts.active.is_empty());
+        assert_eq!(alerts.recent, changes);
+        // Other targets are evaluated separately
+        assert!(engine
+            .update(400, &Update::Ping("b".into(), Ping::new(400, 1.0)))
+            .is_empty());
+    }
+
+    #[test]
+    fn status() {
+        let engine = Engine::new(vec![
+            "temp>70,60/0".parse().unwrap(),
+            "offline>50,0/1m@mc.local".parse().unwrap(),
+        ]);
+        let hw = |temperature| {
+            Update::Hw(hw::Status {
+                temperature,
+                ..Default::default()
+            })
+        };
+        let changes = engine.update(0, &hw(75.0));
+        assert_eq!(changes[0].subject, "system");
+        assert!(engine.update(10, &hw(65.0)).is_empty());
+        assert_eq!(engine.update(20, &hw(55.0))[0].state, State::Resolved);
+
+        let offline = Update::Mc(vec![
+            mc::Status::default("mc.local"),
+            mc::Status::default("other"),
+        ]);
+        let changes = engine.update(0, &offline);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].subject, "mc.local");
+
+        // Back online, the resolve threshold of 0 is reached
+        let online = Update::Mc(vec![mc::Status {
+            version: "1.21".into(),
+            ..mc::Status::default("mc.local")
+        }]);
+        let changes = engine.update(60, &online);
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].state, changes[0].value), (State::Resolved, 0.0));
+        assert!(engine.alerts().active.is_empty());
+    }
+}
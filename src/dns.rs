@@ -0,0 +1,284 @@
+//! DNS resolution latency probe.
+//!
+//! Sends a single query to a specific resolver over UDP and retries over TCP
+//! if the response was truncated.
+
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tracing::warn;
+
+use super::ping::{DnsAnswer, Outcome, Reply};
+use super::target::Dns;
+
+/// Flag of truncated responses
+const TC: u16 = 0x0200;
+/// Flag of responses
+const QR: u16 = 0x8000;
+/// Flag requesting a recursive resolution
+const RD: u16 = 0x0100;
+
+/// Record types that can be given by name
+const TYPES: [(&str, u16); 10] = [
+    ("A", 1),
+    ("NS", 2),
+    ("CNAME", 5),
+    ("SOA", 6),
+    ("PTR", 12),
+    ("MX", 15),
+    ("TXT", 16),
+    ("AAAA", 28),
+    ("SRV", 33),
+    ("ANY", 255),
+];
+
+/// Parses a record type name (e.g. `AAAA`) or number
+pub fn record_type(name: &str) -> Option<u16> {
+    TYPES
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, t)| *t)
+        .or_else(|| name.parse().ok())
+}
+
+/// Queries the resolver of the target and returns the response time,
+/// response code and number of answers.
+pub async fn request(target: &Dns, seq: u16, timeout: Duration) -> Result<Reply, Outcome> {
+    let resolver = tokio::net::lookup_host((target.resolver.as_str(), target.port))
+        .await
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or(Outcome::ResolveFailure)?;
+    let query = encode(seq, &target.name, target.record_type).ok_or(Outcome::ProtocolError)?;
+
+    let start = Instant::now();
+    tokio::time::timeout(timeout, async {
+        let mut tcp = false;
+        let mut response = query_udp(resolver, &query).await.map_err(io_error)?;
+        if parse(seq, &response).is_some_and(|h| h.flags & TC != 0) {
+            tcp = true;
+            response = query_tcp(resolver, &query).await.map_err(io_error)?;
+        }
+        let header = parse(seq, &response).ok_or(Outcome::ProtocolError)?;
+        Ok(Reply {
+            rtt: start.elapsed(),
+            seq,
+            dns: Some(DnsAnswer {
+                rcode: (header.flags & 0x000f) as u8,
+                answers: header.answers,
+                tcp,
+            }),
+            ..Default::default()
+        })
+    })
+    .await
+    .unwrap_or(Err(Outcome::Timeout))
+}
+
+async fn query_udp(resolver: SocketAddr, query: &[u8]) -> io::Result<Vec<u8>> {
+    let local: SocketAddr = if resolver.is_ipv6() {
+        "[::]:0".parse().unwrap()
+    } else {
+        "0.0.0.0:0".parse().unwrap()
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(resolver).await?;
+    socket.send(query).await?;
+
+    let mut buf = vec![0; 4096];
+    loop {
+        let len = socket.recv(&mut buf).await?;
+        // Ignore late responses to previous queries
+        if len >= 2 && buf[..2] == query[..2] {
+            buf.truncate(len);
+            return Ok(buf);
+        }
+    }
+}
+
+async fn query_tcp(resolver: SocketAddr, query: &[u8]) -> io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect(resolver).await?;
+    let mut message = (query.len() as u16).to_be_bytes().to_vec();
+    message.extend_from_slice(query);
+    stream.write_all(&message).await?;
+
+    let len = stream.read_u16().await?;
+    let mut buf = vec![0; len as usize];
+    stream.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+fn io_error(e: io::Error) -> Outcome {
+    match e.kind() {
+        ErrorKind::ConnectionRefused => Outcome::Refused,
+        ErrorKind::ConnectionReset | ErrorKind::UnexpectedEof => Outcome::Reset,
+        ErrorKind::HostUnreachable | ErrorKind::NetworkUnreachable => Outcome::Unreachable,
+        _ => {
+            warn!("dns query failed: {e}");
+            Outcome::ProtocolError
+        }
+    }
+}
+
+/// Encodes a recursive query with a single question
+fn encode(id: u16, name: &str, record_type: u16) -> Option<Vec<u8>> {
+    let mut query = Vec::with_capacity(18 + name.len());
+    query.extend(id.to_be_bytes());
+    query.extend(RD.to_be_bytes());
+    query.extend([0, 1, 0, 0, 0, 0, 0, 0]); // 1 question, no other records
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return None;
+        }
+        query.push(label.len() as u8);
+        query.extend(label.as_bytes());
+    }
+    query.push(0);
+    query.extend(record_type.to_be_bytes());
+    query.extend(1u16.to_be_bytes()); // class IN
+    Some(query)
+}
+
+/// Relevant fields of a response header
+#[derive(Debug, PartialEq)]
+struct Header {
+    flags: u16,
+    answers: u16,
+}
+
+/// Parses the header of the response to the query with the given `id`
+fn parse(id: u16, response: &[u8]) -> Option<Header> {
+    let field = |i: usize| Some(u16::from_be_bytes(response.get(i..i + 2)?.try_into().ok()?));
+    let flags = field(2)?;
+    if field(0)? != id || flags & QR == 0 {
+        return None;
+    }
+    Some(Header {
+        flags,
+        answers: field(6)?,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Builds a response with `answers` A records for the query
+    fn response(query: &[u8], flags: u16, answers: u16) -> Vec<u8> {
+        let mut response = query.to_vec();
+        response[2..4].copy_from_slice(&(QR | RD | 0x0080 | flags).to_be_bytes());
+        response[6..8].copy_from_slice(&answers.to_be_bytes());
+        for i in 0..answers {
+            // Pointer to the question name, A, IN, TTL, 4 byte address
+            response.extend([0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 0, 0]);
+            response.push(i as u8);
+        }
+        response
+    }
+
+    #[test]
+    fn test_encode() {
+        let query = encode(0x1234, "example.com.", 28).unwrap();
+        assert_eq!(
+            query,
+            [
+                0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0, 7, b'e', b'x', b'a', b'm', b'p',
+                b'l', b'e', 3, b'c', b'o', b'm', 0, 0, 28, 0, 1
+            ]
+        );
+        assert_eq!(encode(1, "a..b", 1), None);
+        assert_eq!(record_type("aaaa"), Some(28));
+        assert_eq!(record_type("65"), Some(65));
+        assert_eq!(record_type("XYZ"), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let query = encode(7, "example.com", 1).unwrap();
+        assert_eq!(parse(7, &query), None);
+        let header = parse(7, &response(&query, 3, 0)).unwrap();
+        assert_eq!(header.flags & 0x000f, 3);
+        assert_eq!(header.answers, 0);
+        assert_eq!(parse(8, &response(&query, 0, 2)), None);
+        assert_eq!(parse(7, &query[..4]), None);
+    }
+
+    /// Stub resolver that truncates every UDP response and answers over TCP
+    async fn stub_resolver(truncate: bool) -> SocketAddr {
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp = tokio::net::TcpListener::bind(addr).await.unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            loop {
+                let (len, peer) = udp.recv_from(&mut buf).await.unwrap();
+                let response = if truncate {
+                    response(&buf[..len], TC, 0)
+                } else {
+                    response(&buf[..len], 0, 2)
+                };
+                udp.send_to(&response, peer).await.unwrap();
+            }
+        });
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = tcp.accept().await.unwrap();
+                let len = stream.read_u16().await.unwrap();
+                let mut query = vec![0; len as usize];
+                stream.read_exact(&mut query).await.unwrap();
+                let response = response(&query, 0, 3);
+                stream.write_u16(response.len() as u16).await.unwrap();
+                stream.write_all(&response).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn local_resolver() {
+        let target = |addr: SocketAddr| Dns {
+            url: String::new(),
+            resolver: addr.ip().to_string(),
+            port: addr.port(),
+            name: "example.com".into(),
+            record_type: 1,
+        };
+        let timeout = Duration::from_secs(2);
+
+        let addr = stub_resolver(false).await;
+        let reply = request(&target(addr), 1, timeout).await.unwrap();
+        assert_eq!(
+            reply.dns,
+            Some(DnsAnswer {
+                rcode: 0,
+                answers: 2,
+                tcp: false
+            })
+        );
+
+        let addr = stub_resolver(true).await;
+        let reply = request(&target(addr), 2, timeout).await.unwrap();
+        assert_eq!(
+            reply.dns,
+            Some(DnsAnswer {
+                rcode: 0,
+                answers: 3,
+                tcp: true
+            })
+        );
+
+        // Nobody answers
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let reply = request(
+            &target(silent.local_addr().unwrap()),
+            3,
+            Duration::from_millis(100),
+        )
+        .await;
+        assert_eq!(reply, Err(Outcome::Timeout));
+    }
+}
@@ -0,0 +1,84 @@
+use std::fmt;
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+/// Health of the monitor of a single target
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Status {
+    pub target: String,
+    /// Whether the last tick was performed and logged
+    pub healthy: bool,
+    /// Timestamp of the last successfully logged tick
+    pub last_success: Option<i64>,
+    /// Number of failures since the last successful tick
+    pub consecutive_failures: u32,
+    /// Total number of failures
+    pub failures: u64,
+    /// Description of the last failure
+    pub last_error: Option<String>,
+}
+
+/// Shared health state, updated by the monitor and read by the server
+#[derive(Debug)]
+pub struct Health(Mutex<Status>);
+
+impl Health {
+    pub fn new(target: String) -> Health {
+        Health(Mutex::new(Status {
+            target,
+            // Nothing has failed yet
+            healthy: true,
+            ..Default::default()
+        }))
+    }
+
+    /// Records a successfully logged tick
+    pub fn success(&self, time: i64) {
+        let mut status = self.0.lock().unwrap();
+        status.healthy = true;
+        status.last_success = Some(time);
+        status.consecutive_failures = 0;
+    }
+
+    /// Records a failed tick
+    pub fn failure(&self, error: impl fmt::Display) {
+        let mut status = self.0.lock().unwrap();
+        status.healthy = false;
+        status.consecutive_failures += 1;
+        status.failures += 1;
+        status.last_error = Some(error.to_string());
+    }
+
+    pub fn status(&self) -> Status {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn transitions() {
+        let health = Health::new("1.1.1.1".into());
+        assert!(health.status().healthy);
+
+        health.failure("disk full");
+        health.failure("disk full");
+        let status = health.status();
+        assert!(!status.healthy);
+        assert_eq!(status.consecutive_failures, 2);
+        assert_eq!(status.last_error.as_deref(), Some("disk full"));
+        assert_eq!(status.last_success, None);
+
+        health.success(100);
+        let status = health.status();
+        assert!(status.healthy);
+        assert_eq!(status.consecutive_failures, 0);
+        assert_eq!(status.failures, 2);
+        assert_eq!(status.last_success, Some(100));
+        // The last error is kept for diagnosis
+        assert_eq!(status.last_error.as_deref(), Some("disk full"));
+    }
+}
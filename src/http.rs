@@ -0,0 +1,288 @@
+//! HTTP(S) request timing probe.
+//!
+//! Performs a `GET` request and measures the duration of the individual
+//! phases (name resolution, TCP and TLS handshake, time to first byte).
+//! Also sends the `POST` requests of the webhooks.
+
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::{self, pki_types::ServerName};
+use tokio_rustls::TlsConnector;
+use tracing::warn;
+
+use super::ping::{HttpTiming, Outcome, Reply};
+use super::target::Http;
+
+/// Maximum number of response bytes that are checked for the expected body
+const MAX_RESPONSE: usize = 1 << 20;
+
+static TLS: LazyLock<TlsConnector> = LazyLock::new(|| {
+    let roots = rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .expect("invalid tls protocol versions")
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    TlsConnector::from(Arc::new(config))
+});
+
+/// Performs the request and checks the response against the expectations
+/// of the target.
+pub async fn request(target: &Http, seq: u16, timeout: Duration) -> Result<Reply, Outcome> {
+    tokio::time::timeout(timeout, perform(target, seq))
+        .await
+        .unwrap_or(Err(Outcome::Timeout))
+}
+
+async fn perform(target: &Http, seq: u16) -> Result<Reply, Outcome> {
+    let start = Instant::now();
+    let addr = resolve(target).await?;
+    let resolved = Instant::now();
+
+    let stream = TcpStream::connect(addr).await.map_err(connect_error)?;
+    let connected = Instant::now();
+
+    let (response, tls) = if target.tls {
+        let stream = handshake(target, stream).await?;
+        let handshake = connected.elapsed();
+        (
+            exchange(stream, target, None).await?,
+            Some(millis(handshake)),
+        )
+    } else {
+        (exchange(stream, target, None).await?, None)
+    };
+    let (status, ttfb, body) = response;
+
+    if !target.expect_status.map_or(status < 400, |s| s == status) {
+        warn!("unexpected status {status} from {}", target.url);
+        return Err(Outcome::Mismatch);
+    }
+    if let Some(expected) = &target.expect_body {
+        if !contains(&body, expected.as_bytes()) {
+            warn!("unexpected response body from {}", target.url);
+            return Err(Outcome::Mismatch);
+        }
+    }
+
+    Ok(Reply {
+        rtt: start.elapsed(),
+        seq,
+        http: Some(HttpTiming {
+            status,
+            dns: millis(resolved - start),
+            connect: millis(connected - resolved),
+            tls,
+            ttfb: millis(ttfb),
+        }),
+        ..Default::default()
+    })
+}
+
+/// Sends a `POST` request with the JSON body and returns the status
+pub async fn post(target: &Http, body: &str, timeout: Duration) -> Result<u16, Outcome> {
+    let send = async {
+        let stream = TcpStream::connect(resolve(target).await?)
+            .await
+            .map_err(connect_error)?;
+        let (status, _, _) = if target.tls {
+            exchange(handshake(target, stream).await?, target, Some(body)).await?
+        } else {
+            exchange(stream, target, Some(body)).await?
+        };
+        Ok(status)
+    };
+    tokio::time::timeout(timeout, send)
+        .await
+        .unwrap_or(Err(Outcome::Timeout))
+}
+
+async fn resolve(target: &Http) -> Result<SocketAddr, Outcome> {
+    tokio::net::lookup_host((target.host.as_str(), target.port))
+        .await
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or(Outcome::ResolveFailure)
+}
+
+async fn handshake(target: &Http, stream: TcpStream) -> Result<TlsStream<TcpStream>, Outcome> {
+    let name = ServerName::try_from(target.host.clone()).map_err(|_| Outcome::TlsFailure)?;
+    TLS.connect(name, stream).await.map_err(|e| match e.kind() {
+        ErrorKind::ConnectionReset => Outcome::Reset,
+        _ => {
+            warn!("tls handshake with {} failed: {e}", target.host);
+            Outcome::TlsFailure
+        }
+    })
+}
+
+/// Sends the request (a `POST` if there is a JSON body) and returns the
+/// status, time to first byte and body
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    target: &Http,
+    body: Option<&str>,
+) -> Result<(u16, Duration, Vec<u8>), Outcome> {
+    let host = if target.host.contains(':') {
+        format!("[{}]", target.host)
+    } else {
+        target.host.clone()
+    };
+    let default_port = if target.tls { 443 } else { 80 };
+    let host = if target.port == default_port {
+        host
+    } else {
+        format!("{host}:{}", target.port)
+    };
+    let (method, content) = match body {
+        Some(body) => (
+            "POST",
+            format!(
+                "Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            ),
+        ),
+        None => ("GET", "\r\n".into()),
+    };
+    let request = format!(
+        "{method} {} HTTP/1.1\r\nHost: {host}\r\nUser-Agent: ping-log/{}\r\nAccept: */*\r\nConnection: close\r\n{content}",
+        target.path,
+        env!("CARGO_PKG_VERSION"),
+    );
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(connect_error)?;
+    stream.flush().await.map_err(connect_error)?;
+    let sent = Instant::now();
+
+    let mut response = Vec::new();
+    let mut ttfb = None;
+    let mut buf = [0; 4096];
+    while response.len() < MAX_RESPONSE {
+        match stream.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(n) => {
+                ttfb.get_or_insert_with(|| sent.elapsed());
+                response.extend_from_slice(&buf[..n]);
+            }
+            // Some servers close TLS connections without a close_notify
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof && !response.is_empty() => break,
+            Err(e) => return Err(connect_error(e)),
+        }
+    }
+
+    let ttfb = ttfb.ok_or(Outcome::ProtocolError)?;
+    let (status, body) = parse(&response).ok_or(Outcome::ProtocolError)?;
+    Ok((status, ttfb, body.to_vec()))
+}
+
+/// Parses the status code and returns the body of the response
+fn parse(response: &[u8]) -> Option<(u16, &[u8])> {
+    let line_end = response.iter().position(|&b| b == b'\n')?;
+    let line = std::str::from_utf8(&response[..line_end]).ok()?;
+    let mut parts = line.split_whitespace();
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    let status = parts.next()?.parse().ok()?;
+
+    let body = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map_or(&[][..], |i| &response[i + 4..]);
+    Some((status, body))
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty() || haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+fn connect_error(e: io::Error) -> Outcome {
+    match e.kind() {
+        ErrorKind::ConnectionRefused => Outcome::Refused,
+        ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe => {
+            Outcome::Reset
+        }
+        ErrorKind::TimedOut => Outcome::Timeout,
+        ErrorKind::HostUnreachable | ErrorKind::NetworkUnreachable => Outcome::Unreachable,
+        _ => {
+            warn!("http request failed: {e}");
+            Outcome::ProtocolError
+        }
+    }
+}
+
+fn millis(d: Duration) -> f64 {
+    (d.as_secs_f64() * 10000.0).round() / 10.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use axum::http::StatusCode;
+    use axum::routing::get;
+
+    #[test]
+    fn test_parse() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+        assert_eq!(parse(response), Some((200, &b"ok"[..])));
+        let response = b"HTTP/1.0 404 Not Found\r\n\r\n";
+        assert_eq!(parse(response), Some((404, &b""[..])));
+        assert_eq!(parse(b"SSH-2.0-OpenSSH\r\n"), None);
+        assert_eq!(parse(b"HTTP/1.1 abc\r\n"), None);
+        assert_eq!(parse(b""), None);
+    }
+
+    #[tokio::test]
+    async fn local_server() {
+        let app = axum::Router::new()
+            .route("/", get(|| async { "hello world" }))
+            .route("/missing", get(|| async { StatusCode::NOT_FOUND }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let probe = |url: String| async move {
+            let target: Http = url.parse().unwrap();
+            request(&target, 3, Duration::from_secs(5)).await
+        };
+
+        let reply = probe(format!("http://{addr}/")).await.unwrap();
+        assert_eq!(reply.seq, 3);
+        let timing = reply.http.unwrap();
+        assert_eq!(timing.status, 200);
+        assert_eq!(timing.tls, None);
+        assert!(reply.rtt.as_secs_f64() * 1000.0 >= timing.ttfb);
+
+        assert!(probe(format!("http://{addr}/#body=world")).await.is_ok());
+        assert_eq!(
+            probe(format!("http://{addr}/#body=nope")).await,
+            Err(Outcome::Mismatch)
+        );
+        assert_eq!(
+            probe(format!("http://{addr}/missing")).await,
+            Err(Outcome::Mismatch)
+        );
+        let reply = probe(format!("http://{addr}/missing#status=404")).await;
+        assert_eq!(reply.unwrap().http.unwrap().status, 404);
+
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_addr = closed.local_addr().unwrap();
+        drop(closed);
+        assert_eq!(
+            probe(format!("http://{closed_addr}/")).await,
+            Err(Outcome::Refused)
+        );
+    }
+}
@@ -7,16 +7,16 @@ use tracing::error;
 const TEMPERATURE_FILE: &str = "/sys/class/thermal/thermal_zone0/temp";
 
 /// Describes the system status of the underlaying linux server.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct Status {
     /// CPU load in percent times the number of CPUs.
-    load: f32,
-    /// Current memory consumption.
-    memory_used: f32,
-    /// Total memory installed on the system.
-    memory_total: f32,
+    pub load: f32,
+    /// Current memory consumption in GiB.
+    pub memory_used: f32,
+    /// Total memory installed on the system in GiB.
+    pub memory_total: f32,
     /// CPU temperature.
-    temperature: f32,
+    pub temperature: f32,
 }
 
 impl Status {
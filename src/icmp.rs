@@ -0,0 +1,595 @@
+//! Native ICMP echo requests for IPv4 and IPv6.
+//!
+//! Unprivileged `SOCK_DGRAM` ICMP sockets are used if the kernel allows them
+//! (see `net.ipv4.ping_group_range`), raw sockets otherwise. The sockets
+//! are non-blocking and registered with tokio, so that dropping a request
+//! (e.g. on a timeout) cancels it.
+
+use std::io;
+use std::mem;
+use std::net::{IpAddr, SocketAddr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::{Duration, Instant};
+
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+
+use super::ping::Reply;
+
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_DEST_UNREACHABLE: u8 = 3;
+const ICMPV6_DEST_UNREACHABLE: u8 = 1;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+const ICMP_TIME_EXCEEDED: u8 = 11;
+const ICMPV6_TIME_EXCEEDED: u8 = 3;
+
+/// Size of the echo request including the 8 byte ICMP header
+const PACKET_SIZE: usize = 64;
+
+/// Distinguishes the identifiers of concurrently used raw sockets
+static NEXT_IDENT: AtomicU16 = AtomicU16::new(0);
+
+/// ICMP error message about an echo request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IcmpError {
+    /// Destination unreachable
+    Unreachable,
+    /// The time to live (hop limit) was exceeded in transit
+    TimeExceeded,
+}
+
+/// Response to an echo request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Response {
+    /// Echo reply of the target
+    Reply(Reply),
+    /// Error message of a router on the path (or of the target)
+    Error {
+        from: IpAddr,
+        error: IcmpError,
+        rtt: Duration,
+    },
+}
+
+/// Received packet and its metadata
+struct Packet {
+    len: usize,
+    source: Option<IpAddr>,
+    ttl: Option<u8>,
+    /// ICMP error from the socket error queue (datagram sockets only)
+    error: Option<(IcmpError, IpAddr)>,
+}
+
+/// ICMP echo socket bound to a single address family.
+pub struct Socket {
+    fd: AsyncFd<OwnedFd>,
+    ipv6: bool,
+    /// Raw sockets receive all ICMP packets (including the IPv4 header)
+    raw: bool,
+    ident: u16,
+}
+
+impl Socket {
+    /// Opens an unprivileged ICMP socket, or a raw socket if the former is
+    /// not permitted. Must be called within a tokio runtime.
+    pub fn new(ipv6: bool) -> io::Result<Socket> {
+        let (domain, proto) = if ipv6 {
+            (libc::AF_INET6, libc::IPPROTO_ICMPV6)
+        } else {
+            (libc::AF_INET, libc::IPPROTO_ICMP)
+        };
+
+        let (fd, raw) = match open(domain, libc::SOCK_DGRAM, proto) {
+            Ok(fd) => (fd, false),
+            Err(e) if matches!(e.raw_os_error(), Some(libc::EACCES | libc::EPERM)) => {
+                (open(domain, libc::SOCK_RAW, proto)?, true)
+            }
+            Err(e) => return Err(e),
+        };
+
+        // Request the ttl / hop limit of the replies as control message
+        let (level, name) = if ipv6 {
+            (libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT)
+        } else {
+            (libc::IPPROTO_IP, libc::IP_RECVTTL)
+        };
+        set_option(&fd, level, name, 1 as libc::c_int)?;
+
+        // Datagram sockets only receive ICMP errors through the error queue
+        if !raw {
+            let (level, name) = if ipv6 {
+                (libc::IPPROTO_IPV6, libc::IPV6_RECVERR)
+            } else {
+                (libc::IPPROTO_IP, libc::IP_RECVERR)
+            };
+            set_option(&fd, level, name, 1 as libc::c_int)?;
+        }
+
+        // SAFETY: the descriptor is owned and thus stays open until dropped
+        let interest = Interest::READABLE | Interest::WRITABLE | Interest::ERROR;
+        let fd = unsafe { AsyncFd::register_with_interest(fd, interest)? };
+        Ok(Socket {
+            fd,
+            ipv6,
+            raw,
+            ident: (std::process::id() as u16)
+                .wrapping_add(NEXT_IDENT.fetch_add(1, Ordering::Relaxed)),
+        })
+    }
+
+    /// Sets the time to live (hop limit) of the following requests.
+    pub fn set_ttl(&self, ttl: u8) -> io::Result<()> {
+        let (level, name) = if self.ipv6 {
+            (libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS)
+        } else {
+            (libc::IPPROTO_IP, libc::IP_TTL)
+        };
+        set_option(self.fd.get_ref(), level, name, ttl as libc::c_int)
+    }
+
+    /// Sends an echo request to `addr` and waits up to `timeout` for the reply.
+    ///
+    /// ICMP error messages are reported as [`io::ErrorKind::HostUnreachable`].
+    pub async fn echo(&self, addr: IpAddr, seq: u16, timeout: Duration) -> io::Result<Reply> {
+        match self.send(addr, seq, timeout).await? {
+            Response::Reply(reply) => Ok(reply),
+            Response::Error { .. } => Err(io::ErrorKind::HostUnreachable.into()),
+        }
+    }
+
+    /// Sends an echo request to `addr` and waits up to `timeout` for the reply
+    /// or an error message.
+    pub async fn send(&self, addr: IpAddr, seq: u16, timeout: Duration) -> io::Result<Response> {
+        if addr.is_ipv6() != self.ipv6 {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        let packet = request(self.ipv6, self.ident, seq);
+        let (storage, len) = sockaddr(SocketAddr::new(addr, 0));
+
+        let start = Instant::now();
+        // A full send buffer is the only reason to wait for writability
+        self.fd
+            .async_io(Interest::WRITABLE, |fd| {
+                let sent = unsafe {
+                    libc::sendto(
+                        fd.as_raw_fd(),
+                        packet.as_ptr().cast(),
+                        packet.len(),
+                        0,
+                        (&raw const storage).cast(),
+                        len,
+                    )
+                };
+                if sent < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(())
+                }
+            })
+            .await?;
+
+        let deadline = tokio::time::Instant::from_std(start + timeout);
+        let mut buf = [0; 1024];
+        loop {
+            let packet = tokio::time::timeout_at(deadline, self.receive(&mut buf))
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+            let rtt = start.elapsed();
+
+            let mut data = &buf[..packet.len];
+            if let Some((error, from)) = packet.error {
+                // The error queue returns the original echo request
+                if parse_request(self.ipv6, data) == Some(seq) {
+                    return Ok(Response::Error { from, error, rtt });
+                }
+                continue;
+            }
+
+            let mut ttl = packet.ttl;
+            if self.raw && !self.ipv6 {
+                // Raw IPv4 sockets include the IP header
+                let Some((header_ttl, payload)) = strip_ipv4_header(data) else {
+                    continue;
+                };
+                ttl = ttl.or(Some(header_ttl));
+                data = payload;
+            }
+
+            // Datagram sockets rewrite the identifier and filter the replies
+            let ident = self.raw.then_some(self.ident);
+
+            // Error messages are sent by routers on the path to the target
+            if let Some((error, dest, error_seq)) = parse_error(self.ipv6, ident, data) {
+                if let Some(from) = packet.source.filter(|_| dest == addr && error_seq == seq) {
+                    return Ok(Response::Error { from, error, rtt });
+                }
+                continue;
+            }
+
+            // Raw sockets of concurrent requests receive each others replies
+            if packet.source != Some(addr) {
+                continue;
+            }
+
+            if let Some(reply_seq) = parse_reply(self.ipv6, ident, data) {
+                if reply_seq == seq {
+                    return Ok(Response::Reply(Reply {
+                        rtt,
+                        ttl,
+                        seq,
+                        ..Default::default()
+                    }));
+                }
+            }
+        }
+    }
+
+    /// Waits for the next packet or, for datagram sockets, ICMP error.
+    async fn receive(&self, buf: &mut [u8]) -> io::Result<Packet> {
+        loop {
+            let mut guard = self.fd.ready(Interest::READABLE | Interest::ERROR).await?;
+            let result = match self.recv(buf, 0) {
+                // A pending ICMP error is reported as socket error first, or
+                // only signalled as error readiness
+                Err(e) if !self.raw => self.recv(buf, libc::MSG_ERRQUEUE).or(Err(e)),
+                result => result,
+            };
+            match result {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => guard.clear_ready(),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                result => return result,
+            }
+        }
+    }
+
+    /// Receives a single packet, its source and the ttl / hop limit or
+    /// socket error control messages.
+    fn recv(&self, buf: &mut [u8], flags: libc::c_int) -> io::Result<Packet> {
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr().cast(),
+            iov_len: buf.len(),
+        };
+        let mut control = [0u64; 32];
+        let mut source: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_name = (&raw mut source).cast();
+        msg.msg_namelen = mem::size_of_val(&source) as _;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = mem::size_of_val(&control) as _;
+
+        let len = unsafe { libc::recvmsg(self.fd.as_raw_fd(), &mut msg, flags) };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut ttl = None;
+        let mut error = None;
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                let (level, ty) = ((*cmsg).cmsg_level, (*cmsg).cmsg_type);
+                if (level == libc::IPPROTO_IP && ty == libc::IP_TTL)
+                    || (level == libc::IPPROTO_IPV6 && ty == libc::IPV6_HOPLIMIT)
+                {
+                    let value = libc::CMSG_DATA(cmsg).cast::<libc::c_int>().read_unaligned();
+                    ttl = u8::try_from(value).ok();
+                } else if (level == libc::IPPROTO_IP && ty == libc::IP_RECVERR)
+                    || (level == libc::IPPROTO_IPV6 && ty == libc::IPV6_RECVERR)
+                {
+                    let ee = libc::CMSG_DATA(cmsg).cast::<libc::sock_extended_err>();
+                    let err = ee.read_unaligned();
+                    let offender = libc::SO_EE_OFFENDER(ee)
+                        .cast::<libc::sockaddr_storage>()
+                        .read_unaligned();
+                    let kind = match (err.ee_origin, err.ee_type) {
+                        (libc::SO_EE_ORIGIN_ICMP, ICMP_DEST_UNREACHABLE)
+                        | (libc::SO_EE_ORIGIN_ICMP6, ICMPV6_DEST_UNREACHABLE) => {
+                            Some(IcmpError::Unreachable)
+                        }
+                        (libc::SO_EE_ORIGIN_ICMP, ICMP_TIME_EXCEEDED)
+                        | (libc::SO_EE_ORIGIN_ICMP6, ICMPV6_TIME_EXCEEDED) => {
+                            Some(IcmpError::TimeExceeded)
+                        }
+                        _ => None,
+                    };
+                    error = kind.zip(ip_addr(&offender));
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+        Ok(Packet {
+            len: len as usize,
+            source: ip_addr(&source),
+            ttl,
+            error,
+        })
+    }
+}
+
+fn open(domain: libc::c_int, ty: libc::c_int, proto: libc::c_int) -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::socket(domain, ty | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC, proto) };
+    if fd < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+}
+
+fn set_option<T>(fd: &OwnedFd, level: libc::c_int, name: libc::c_int, value: T) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            level,
+            name,
+            (&raw const value).cast(),
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn sockaddr(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            let sin = libc::sockaddr_in {
+                sin_family: libc::AF_INET as _,
+                sin_port: addr.port().to_be(),
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from(*addr.ip()).to_be(),
+                },
+                sin_zero: [0; 8],
+            };
+            unsafe { (&raw mut storage).cast::<libc::sockaddr_in>().write(sin) };
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let sin6 = libc::sockaddr_in6 {
+                sin6_family: libc::AF_INET6 as _,
+                sin6_port: addr.port().to_be(),
+                sin6_flowinfo: addr.flowinfo(),
+                sin6_addr: libc::in6_addr {
+                    s6_addr: addr.ip().octets(),
+                },
+                sin6_scope_id: addr.scope_id(),
+            };
+            unsafe { (&raw mut storage).cast::<libc::sockaddr_in6>().write(sin6) };
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+fn ip_addr(storage: &libc::sockaddr_storage) -> Option<IpAddr> {
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let sin =
+                unsafe { &*(storage as *const libc::sockaddr_storage).cast::<libc::sockaddr_in>() };
+            Some(IpAddr::from(
+                u32::from_be(sin.sin_addr.s_addr).to_be_bytes(),
+            ))
+        }
+        libc::AF_INET6 => {
+            let sin6 = unsafe {
+                &*(storage as *const libc::sockaddr_storage).cast::<libc::sockaddr_in6>()
+            };
+            Some(IpAddr::from(sin6.sin6_addr.s6_addr))
+        }
+        _ => None,
+    }
+}
+
+/// Builds an echo request packet.
+///
+/// The kernel computes the checksum for ICMPv6, so it is only set for IPv4.
+fn request(ipv6: bool, ident: u16, seq: u16) -> [u8; PACKET_SIZE] {
+    let mut packet = [0; PACKET_SIZE];
+    packet[0] = if ipv6 {
+        ICMPV6_ECHO_REQUEST
+    } else {
+        ICMP_ECHO_REQUEST
+    };
+    packet[4..6].copy_from_slice(&ident.to_be_bytes());
+    packet[6..8].copy_from_slice(&seq.to_be_bytes());
+    for (i, b) in packet[8..].iter_mut().enumerate() {
+        *b = i as u8;
+    }
+    if !ipv6 {
+        let sum = checksum(&packet);
+        packet[2..4].copy_from_slice(&sum.to_be_bytes());
+    }
+    packet
+}
+
+/// Parses an echo reply and returns its sequence number.
+///
+/// If `ident` is given, replies with other identifiers are ignored.
+fn parse_reply(ipv6: bool, ident: Option<u16>, packet: &[u8]) -> Option<u16> {
+    let reply = if ipv6 {
+        ICMPV6_ECHO_REPLY
+    } else {
+        ICMP_ECHO_REPLY
+    };
+    if packet.len() < 8 || packet[0] != reply || packet[1] != 0 {
+        return None;
+    }
+    let reply_ident = u16::from_be_bytes([packet[4], packet[5]]);
+    if ident.is_some_and(|ident| ident != reply_ident) {
+        return None;
+    }
+    Some(u16::from_be_bytes([packet[6], packet[7]]))
+}
+
+/// Parses an echo request and returns its sequence number.
+fn parse_request(ipv6: bool, packet: &[u8]) -> Option<u16> {
+    let request = if ipv6 {
+        ICMPV6_ECHO_REQUEST
+    } else {
+        ICMP_ECHO_REQUEST
+    };
+    if packet.len() < 8 || packet[0] != request {
+        return None;
+    }
+    Some(u16::from_be_bytes([packet[6], packet[7]]))
+}
+
+/// Parses a destination unreachable or time exceeded message and returns the
+/// destination and sequence number of the original echo request.
+fn parse_error(ipv6: bool, ident: Option<u16>, packet: &[u8]) -> Option<(IcmpError, IpAddr, u16)> {
+    let error = match (ipv6, *packet.first()?) {
+        (false, ICMP_DEST_UNREACHABLE) | (true, ICMPV6_DEST_UNREACHABLE) => IcmpError::Unreachable,
+        (false, ICMP_TIME_EXCEEDED) | (true, ICMPV6_TIME_EXCEEDED) => IcmpError::TimeExceeded,
+        _ => return None,
+    };
+
+    // The original IP header and the first 8 bytes of its payload follow
+    let inner = packet.get(8..)?;
+    let (dest, original) = if ipv6 {
+        let dest: [u8; 16] = inner.get(24..40)?.try_into().ok()?;
+        (IpAddr::from(dest), inner.get(40..)?)
+    } else {
+        let dest: [u8; 4] = inner.get(16..20)?.try_into().ok()?;
+        (IpAddr::from(dest), strip_ipv4_header(inner)?.1)
+    };
+
+    let seq = parse_request(ipv6, original)?;
+    let original_ident = u16::from_be_bytes([original[4], original[5]]);
+    if ident.is_some_and(|ident| ident != original_ident) {
+        return None;
+    }
+    Some((error, dest, seq))
+}
+
+/// Returns the ttl and payload of an IPv4 packet.
+fn strip_ipv4_header(packet: &[u8]) -> Option<(u8, &[u8])> {
+    let header_len = (*packet.first()? & 0x0f) as usize * 4;
+    if packet.len() < header_len || header_len < 20 {
+        return None;
+    }
+    Some((packet[8], &packet[header_len..]))
+}
+
+/// Internet checksum (RFC 1071)
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_checksum() {
+        let packet = request(false, 0x1234, 7);
+        assert_eq!(checksum(&packet), 0);
+        assert_eq!(checksum(&[0x00, 0x01, 0xf2, 0x03, 0xf4]), !0xe605);
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        let mut packet = request(false, 0x1234, 7);
+        assert_eq!(parse_reply(false, None, &packet), None);
+
+        packet[0] = ICMP_ECHO_REPLY;
+        assert_eq!(parse_reply(false, None, &packet), Some(7));
+        assert_eq!(parse_reply(false, Some(0x1234), &packet), Some(7));
+        assert_eq!(parse_reply(false, Some(0x4321), &packet), None);
+        assert_eq!(parse_reply(true, None, &packet), None);
+
+        let mut packet = request(true, 0x1234, 9);
+        packet[0] = ICMPV6_ECHO_REPLY;
+        assert_eq!(parse_reply(true, Some(0x1234), &packet), Some(9));
+        assert_eq!(parse_reply(true, None, &packet[..4]), None);
+    }
+
+    #[test]
+    fn test_parse_error() {
+        use IcmpError::*;
+
+        // IPv4: ICMP header, original IP header, original echo request
+        let mut packet = vec![ICMP_DEST_UNREACHABLE, 1, 0, 0, 0, 0, 0, 0];
+        packet.extend([0x45, 0, 0, 84, 0, 0, 0, 0, 64, 1, 0, 0]);
+        packet.extend([192, 168, 0, 2, 10, 0, 0, 1]);
+        packet.extend(&request(false, 0x1234, 7)[..8]);
+        let dest = IpAddr::from([10, 0, 0, 1]);
+        assert_eq!(
+            parse_error(false, None, &packet),
+            Some((Unreachable, dest, 7))
+        );
+        assert_eq!(
+            parse_error(false, Some(0x1234), &packet),
+            Some((Unreachable, dest, 7))
+        );
+        assert_eq!(parse_error(false, Some(0x4321), &packet), None);
+        assert_eq!(parse_error(false, None, &packet[..30]), None);
+
+        packet[0] = ICMP_TIME_EXCEEDED;
+        assert_eq!(
+            parse_error(false, None, &packet),
+            Some((TimeExceeded, dest, 7))
+        );
+
+        let mut reply = request(false, 0x1234, 7);
+        reply[0] = ICMP_ECHO_REPLY;
+        assert_eq!(parse_error(false, None, &reply), None);
+
+        // IPv6: ICMPv6 header, original IPv6 header, original echo request
+        let dest = IpAddr::from([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1]);
+        let mut packet = vec![ICMPV6_TIME_EXCEEDED, 0, 0, 0, 0, 0, 0, 0];
+        packet.extend([0x60, 0, 0, 0, 0, 64, 58, 64]);
+        packet.extend([0; 16]);
+        let IpAddr::V6(dest_v6) = dest else {
+            unreachable!()
+        };
+        packet.extend(dest_v6.octets());
+        packet.extend(&request(true, 0x1234, 9)[..8]);
+        assert_eq!(
+            parse_error(true, Some(0x1234), &packet),
+            Some((TimeExceeded, dest, 9))
+        );
+        packet[0] = ICMPV6_DEST_UNREACHABLE;
+        assert_eq!(
+            parse_error(true, Some(0x1234), &packet),
+            Some((Unreachable, dest, 9))
+        );
+    }
+
+    #[test]
+    fn test_sockaddr() {
+        for addr in ["127.0.0.1:0", "[::1]:0", "[2001:db8::7]:0"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            assert_eq!(ip_addr(&sockaddr(addr).0), Some(addr.ip()));
+        }
+    }
+
+    #[test]
+    fn test_strip_ipv4_header() {
+        let mut packet = vec![0x45, 0, 0, 84, 0, 0, 0, 0, 57, 1, 0, 0];
+        packet.extend([0; 8]);
+        packet.extend([ICMP_ECHO_REPLY, 0, 0, 0]);
+        let (ttl, payload) = strip_ipv4_header(&packet).unwrap();
+        assert_eq!(ttl, 57);
+        assert_eq!(payload, [ICMP_ECHO_REPLY, 0, 0, 0]);
+
+        assert_eq!(strip_ipv4_header(&packet[..10]), None);
+        assert_eq!(strip_ipv4_header(&[]), None);
+    }
+}
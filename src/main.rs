@@ -7,33 +7,114 @@
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::Duration;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use tracing::{error, info, warn};
 
+mod alert;
+mod dns;
+mod health;
+mod http;
 mod hw;
+#[cfg(target_os = "linux")]
+mod icmp;
 mod mc;
+mod metrics;
+mod mqtt;
+mod outage;
 mod ping;
+mod ping_aggregate;
 mod ping_request;
 mod ping_stats;
+mod report;
+mod retention;
+mod rollup;
+mod scheduler;
 mod server;
+mod sqlite;
+mod storage;
+mod stream;
+mod target;
+mod trace;
+mod verify;
+mod webhook;
 
 /// Command line options
 #[derive(Debug, Parser)]
 #[command(author, version, about)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Time between ping requests
     #[arg(short, long, default_value_t = 60)]
     interval: u64,
 
-    /// Address or url of the ping target server
-    #[arg(short, long, default_value = "1.1.1.1")]
-    ping_host: String,
+    /// Addresses of the ping target servers or probe urls (`tcp://`, `http(s)://`, `dns://`)
+    #[arg(short, long, default_value = "1.1.1.1", value_delimiter = ',')]
+    ping_host: Vec<target::Target>,
+
+    /// Implementation used for the ping requests
+    #[arg(long, value_enum, default_value_t = ping_request::Backend::default())]
+    backend: ping_request::Backend,
+
+    /// Number of echo requests per interval
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    count: u16,
+
+    /// Time between the echo requests of an interval in milliseconds
+    #[arg(long, default_value_t = 200)]
+    spacing: u64,
+
+    /// Trace the path to a target on packet loss
+    #[arg(long)]
+    trace: bool,
+
+    /// Also trace the path if the response time exceeds this many milliseconds
+    #[arg(long, requires = "trace")]
+    trace_threshold: Option<f64>,
+
+    /// Minimum time between two traces of a target in seconds
+    #[arg(long, default_value_t = 600)]
+    trace_cooldown: u64,
+
+    /// Maximum number of concurrently running probes
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u16).range(1..))]
+    max_probes: u16,
+
+    /// Remove logs older than this many days (0 keeps them forever)
+    #[arg(long, default_value_t = 56)]
+    retention_days: u32,
+
+    /// Remove the oldest logs while all logs are larger than this (e.g. `500M`)
+    #[arg(long)]
+    retention_size: Option<retention::Size>,
+
+    /// Remove hourly rollups older than this many days (0 keeps them forever)
+    #[arg(long, default_value_t = 365)]
+    retention_hourly: u32,
+
+    /// Remove daily rollups older than this many days (0 keeps them forever)
+    #[arg(long, default_value_t = 0)]
+    retention_daily: u32,
+
+    /// Only print which logs the retention policy would remove and exit
+    #[arg(long)]
+    retention_dry_run: bool,
 
     /// Filepath to the loggin directory
     #[arg(short, long, default_value = "log")]
     logs: PathBuf,
 
+    /// Where the pings are stored
+    #[arg(long, value_enum, default_value_t = storage::Backend::Files)]
+    storage: storage::Backend,
+
+    /// Filepath to the SQLite database (`<logs>/ping-log.db` by default)
+    #[arg(long)]
+    database: Option<PathBuf>,
+
     /// Filepath to the web directory
     #[arg(long, default_value = "ping-view/build")]
     web: PathBuf,
@@ -45,6 +126,73 @@ struct Args {
     /// Address and port of this webserver
     #[arg(short, long)]
     mc_hosts: Vec<String>,
+
+    /// Alert rule like `loss>5,2/10m@1.1.1.1` (can be repeated)
+    #[arg(long = "alert")]
+    alerts: Vec<alert::Rule>,
+
+    /// Number of consecutive lost pings that are an outage
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u16).range(1..))]
+    outage_threshold: u16,
+
+    /// Promised availability in percent for the reports
+    #[arg(long, default_value_t = 99.9)]
+    sla_availability: f64,
+
+    /// Promised maximum response time in ms for the reports
+    #[arg(long, default_value_t = 30.0)]
+    sla_latency: f64,
+
+    /// Url that is notified about the alerts and outages with a JSON `POST` (can be repeated)
+    #[arg(long = "webhook")]
+    webhooks: Vec<target::Http>,
+
+    /// Payload of the webhooks with `{{field}}` placeholders (`@<file>` to read it)
+    #[arg(long)]
+    webhook_template: Option<String>,
+
+    /// MQTT broker that receives the pings and status updates (e.g. `mqtt://localhost`)
+    #[arg(long)]
+    mqtt: Option<mqtt::Broker>,
+
+    /// First level of the MQTT topics
+    #[arg(long, default_value = "ping-log")]
+    mqtt_prefix: String,
+
+    /// Publish the MQTT messages as retained
+    #[arg(long, requires = "mqtt")]
+    mqtt_retain: bool,
+
+    /// Publish Home Assistant discovery configs under this topic prefix
+    #[arg(long, requires = "mqtt", num_args = 0..=1, default_missing_value = "homeassistant")]
+    mqtt_discovery: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Imports the log files of the ping hosts into the SQLite database
+    Migrate,
+    /// Reports corrupt lines in the log files
+    Verify,
+    /// Removes corrupt lines from the log files (stop the server before)
+    Repair,
+    /// Writes the monthly availability report of the ping hosts
+    Report {
+        /// First month (e.g. `2026-01`), defaults to `--to`
+        #[arg(long)]
+        from: Option<report::Month>,
+        /// Last month, defaults to the current one
+        #[arg(long)]
+        to: Option<report::Month>,
+        /// Time zone of the months (`local`, `UTC`, `+02:00` or `Europe/Berlin`)
+        #[arg(long, default_value = "local")]
+        tz: ping_aggregate::Zone,
+        #[arg(long, value_enum, default_value_t = report::Format::Markdown)]
+        format: report::Format,
+        /// Output file, stdout by default
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
 }
 
 #[tokio::main]
@@ -53,14 +201,153 @@ async fn main() {
 
     let args = Args::parse();
 
+    let policy = retention::Policy {
+        max_age: (args.retention_days > 0).then_some(args.retention_days),
+        max_size: args.retention_size.map(|s| s.0),
+        hourly_age: (args.retention_hourly > 0).then_some(args.retention_hourly),
+        daily_age: (args.retention_daily > 0).then_some(args.retention_daily),
+    };
+    let database = args
+        .database
+        .clone()
+        .unwrap_or_else(|| args.logs.join("ping-log.db"));
+    if let Some(target) = args.ping_host.first() {
+        // Logs of older versions are adopted by the first target (before they are
+        // migrated)
+        match ping_stats::adopt_legacy_logs(&args.logs, &target.to_string()) {
+            Ok(0) => {}
+            Ok(count) => info!("moved {count} legacy logs to the logs of {target}"),
+            Err(e) => error!("could not move the legacy logs to {target}: {e}"),
+        }
+    }
+
+    match &args.command {
+        Some(Command::Migrate) => {
+            let db = match sqlite::Sqlite::open(&database) {
+                Ok(db) => db,
+                Err(e) => return error!("could not open {}: {e}", database.display()),
+            };
+            for target in args.ping_host.iter().map(ToString::to_string) {
+                let dir = ping_stats::target_dir(&args.logs, &target);
+                match db.import(&target, &dir) {
+                    Ok(count) => println!("imported {count} pings of {target}"),
+                    Err(e) => error!("could not import {target}: {e}"),
+                }
+            }
+            return;
+        }
+        Some(command @ (Command::Verify | Command::Repair)) => {
+            let report = verify::run(&args.logs, matches!(command, Command::Repair));
+            print!("{report}");
+            if !report.errors.is_empty() || (!report.repair && !report.damaged.is_empty()) {
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Report { .. }) | None => {}
+    }
+
+    let storage: Arc<dyn storage::Storage> = match args.storage {
+        storage::Backend::Files => Arc::new(storage::Files::new(args.logs.clone())),
+        storage::Backend::Sqlite => match sqlite::Sqlite::open(&database) {
+            Ok(db) => Arc::new(db),
+            Err(e) => return error!("could not open {}: {e}", database.display()),
+        },
+    };
+    let budget = report::Budget {
+        availability: args.sla_availability,
+        latency: args.sla_latency,
+    };
+    if let Some(Command::Report {
+        from,
+        to,
+        tz,
+        format,
+        output,
+    }) = args.command
     {
-        // Ping reqest thread
-        let log_dir = args.logs.clone();
-        let interval = args.interval;
-        let ping_host = args.ping_host;
+        let now = chrono::Local::now().timestamp();
+        let to = to.unwrap_or_else(|| report::Month::of(now, &tz).expect("current month"));
+        let from = from.unwrap_or(to);
+        if !report::valid_range(from, to) {
+            error!(
+                "--from must not be after --to and at most {} months before",
+                report::MAX_MONTHS - 1
+            );
+            std::process::exit(1);
+        }
+        let reports: Vec<_> = args
+            .ping_host
+            .iter()
+            .map(|target| {
+                report::generate(
+                    storage.as_ref(),
+                    &target.to_string(),
+                    (from, to),
+                    &tz,
+                    budget,
+                    args.outage_threshold as usize,
+                    now,
+                )
+            })
+            .collect();
+        let document = report::render(&reports, format);
+        match output {
+            Some(path) => {
+                if let Err(e) = std::fs::write(&path, document) {
+                    error!("could not write {}: {e}", path.display());
+                    std::process::exit(1);
+                }
+            }
+            None => print!("{document}"),
+        }
+        return;
+    }
+    if args.retention_dry_run {
+        print!("{}", storage.retain(policy, true));
+        return;
+    }
+    tokio::spawn(retention::run(storage.clone(), policy));
 
-        tokio::spawn(async move { ping_request::monitor(&ping_host, interval, &log_dir).await });
+    if args.trace && cfg!(not(target_os = "linux")) {
+        warn!("tracing is only supported on Linux");
+    }
+    let settings = ping_request::Settings {
+        interval: args.interval,
+        backend: args.backend,
+        count: args.count,
+        spacing: Duration::from_millis(args.spacing),
+        trace: (args.trace && cfg!(target_os = "linux")).then_some(ping_request::TraceSettings {
+            threshold: args.trace_threshold,
+            cooldown: Duration::from_secs(args.trace_cooldown),
+        }),
+    };
+    let scheduler = Arc::new(scheduler::Scheduler::new(args.max_probes as usize));
+    let hub = Arc::new(stream::Hub::new(stream::CAPACITY));
+    let shared = ping_request::Shared {
+        scheduler: scheduler.clone(),
+        storage: storage.clone(),
+        hub: hub.clone(),
     };
+    let mut health = Vec::with_capacity(args.ping_host.len());
+    let mut probes = Vec::with_capacity(args.ping_host.len());
+    for target in &args.ping_host {
+        // Ping reqest thread
+        let log_dir = ping_stats::target_dir(&args.logs, &target.to_string());
+        let target_health = Arc::new(health::Health::new(target.to_string()));
+        health.push(target_health.clone());
+        let target_metrics = Arc::new(metrics::ProbeMetrics::new(target.to_string()));
+        probes.push(target_metrics.clone());
+
+        tokio::spawn(ping_request::supervise(
+            target.clone(),
+            log_dir,
+            settings,
+            shared.clone(),
+            target_health,
+            target_metrics,
+        ));
+    }
 
     let mc_state = Arc::new(RwLock::new(Vec::new()));
     if !args.mc_hosts.is_empty() {
@@ -68,18 +355,82 @@ async fn main() {
         let interval = args.interval;
         let mc_hosts = args.mc_hosts.clone();
         let mc_state = mc_state.clone();
+        let scheduler = scheduler.clone();
+        let hub = hub.clone();
 
         tokio::spawn(async move {
             loop {
-                mc::Status::refresh(&mc_state, &mc_hosts).await;
-
-                let epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-                let next =
-                    Duration::from_secs(((epoch.as_secs() + interval) / interval) * interval);
+                mc::Status::refresh(&mc_state, &mc_hosts, &scheduler).await;
+                let status = mc_state.read().unwrap().clone();
+                hub.publish(stream::Update::Mc(status));
+                scheduler.tick(interval).await;
+            }
+        });
+    };
+    {
+        // Hardware status updates for the live stream
+        let interval = args.interval;
+        let scheduler = scheduler.clone();
+        let hub = hub.clone();
 
-                tokio::time::sleep(next - epoch).await;
+        tokio::spawn(async move {
+            loop {
+                scheduler.tick(interval).await;
+                hub.publish(stream::Update::Hw(hw::Status::request()));
             }
         });
+    }
+    let alerts = Arc::new(alert::Engine::new(args.alerts));
+    if !alerts.is_empty() {
+        tokio::spawn(alert::run(alerts.clone(), hub.clone()));
+    }
+    let targets: Vec<String> = args.ping_host.iter().map(ToString::to_string).collect();
+    let outages = Arc::new(outage::Detector::new(
+        args.logs.clone(),
+        args.outage_threshold as usize,
+        &targets,
+    ));
+    tokio::spawn(outage::run(outages.clone(), hub.clone()));
+    let template = match args.webhook_template {
+        Some(template) => match template.strip_prefix('@') {
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(template) => Some(template),
+                Err(e) => return error!("could not read the webhook template {path}: {e}"),
+            },
+            None => Some(template),
+        },
+        None => None,
+    };
+    let webhooks =
+        webhook::Webhooks::new(args.webhooks, template, args.logs.join(webhook::QUEUE_FILE));
+    if !webhooks.is_empty() {
+        tokio::spawn(webhook::run(Arc::new(webhooks), hub.clone()));
+    }
+    if let Some(broker) = args.mqtt {
+        let mqtt = mqtt::Mqtt {
+            broker,
+            prefix: args.mqtt_prefix,
+            retain: args.mqtt_retain,
+            discovery: args.mqtt_discovery,
+            targets: targets.clone(),
+            mc_hosts: args.mc_hosts.clone(),
+        };
+        tokio::spawn(mqtt::run(mqtt, hub.clone()));
+    }
+
+    let state = server::AppState {
+        log_dir: args.logs,
+        targets,
+        mc_hosts: mc_state,
+        web_dir: args.web,
+        scheduler,
+        health,
+        probes,
+        storage,
+        hub,
+        alerts,
+        outages,
+        budget,
     };
-    server::run(args.web_host, args.logs, args.web, mc_state).await
+    server::run(args.web_host, state).await
 }
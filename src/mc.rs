@@ -1,31 +1,43 @@
+use std::io;
 use std::io::ErrorKind::InvalidData;
-use std::io::{self, Read, Write};
-use std::net::{TcpStream, ToSocketAddrs};
 use std::sync::RwLock;
 use std::time::Duration;
 
 use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::scheduler::Scheduler;
+
+/// Time to wait for the connection to the server
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(100);
+/// Time to wait for the complete status request
+const TIMEOUT: Duration = Duration::from_secs(1);
 
 /// Describes the status of a minecraft server.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Status {
-    addr: String,
-    version: String,
-    description: String,
-    players: usize,
-    max_players: usize,
+    pub addr: String,
+    pub version: String,
+    pub description: String,
+    pub players: usize,
+    pub max_players: usize,
 }
 
 impl Status {
     /// Performs server ping requests and updates the cache.
-    pub async fn refresh<S: AsRef<str>>(state: &RwLock<Vec<Status>>, addresses: &[S]) {
+    pub async fn refresh<S: AsRef<str>>(
+        state: &RwLock<Vec<Status>>,
+        addresses: &[S],
+        scheduler: &Scheduler,
+    ) {
         let mut current_status = Vec::with_capacity(addresses.len());
         for addr in addresses {
-            current_status.push(
-                Status::request(addr.as_ref())
-                    .await
-                    .unwrap_or_else(|_| Status::default(addr.as_ref())),
-            );
+            let status = scheduler.run(TIMEOUT, Status::request(addr.as_ref())).await;
+            current_status.push(match status {
+                Some(Ok(status)) => status,
+                _ => Status::default(addr.as_ref()),
+            });
         }
         let mut status = state.write().unwrap();
         *status = current_status;
@@ -38,15 +50,18 @@ impl Status {
     /// - 01: server list ping's payload (always 1)
     /// - ... optional data
     pub async fn request(addr: &str) -> io::Result<Status> {
-        let socket_addr = ToSocketAddrs::to_socket_addrs(&addr)?
+        let socket_addr = tokio::net::lookup_host(addr)
+            .await?
             .next()
             .ok_or(io::ErrorKind::AddrNotAvailable)?;
 
         let response = {
-            let mut stream = TcpStream::connect_timeout(&socket_addr, Duration::from_millis(100))?;
-            stream.write_all(&[0xfe, 0x01])?;
+            let mut stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(socket_addr))
+                .await
+                .map_err(|_| io::ErrorKind::TimedOut)??;
+            stream.write_all(&[0xfe, 0x01]).await?;
             let mut response = [0; 256];
-            let _ = stream.read(&mut response[..])?;
+            let _ = stream.read(&mut response[..]).await?;
             response
         };
         Status::parse(addr, &response)
@@ -114,8 +129,13 @@ impl Status {
         })
     }
 
+    /// Whether the server answered the last status request.
+    pub fn online(&self) -> bool {
+        !self.version.is_empty()
+    }
+
     /// Default status when a server is offline.
-    fn default(addr: &str) -> Status {
+    pub fn default(addr: &str) -> Status {
         Status {
             addr: addr.strip_suffix(":25565").unwrap_or(addr).into(),
             version: String::new(),
@@ -0,0 +1,364 @@
+//! Prometheus metrics in the text exposition format.
+//!
+//! All metric names are prefixed with `ping_log_` and durations are given
+//! in seconds, as recommended by the Prometheus naming conventions.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+use super::health::Health;
+use super::ping::{Outcome, Ping};
+use super::{hw, mc, ping_stats, scheduler};
+
+/// Number of intervals over which the loss ratio is computed
+const LOSS_WINDOW: usize = 60;
+/// Upper bounds of the response time histogram buckets in seconds
+const RTT_BUCKETS: [f64; 13] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Probe results of a single target, updated by its monitor
+#[derive(Debug)]
+pub struct ProbeMetrics {
+    target: String,
+    state: Mutex<ProbeState>,
+}
+
+#[derive(Debug, Default)]
+struct ProbeState {
+    last: Option<Ping>,
+    losses: VecDeque<f64>,
+    outcomes: BTreeMap<&'static str, u64>,
+    /// Non-cumulative counts of the histogram buckets (and +Inf)
+    buckets: [u64; RTT_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl ProbeMetrics {
+    pub fn new(target: String) -> ProbeMetrics {
+        ProbeMetrics {
+            target,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Records the result of an interval
+    pub fn record(&self, ping: &Ping) {
+        let mut state = self.state.lock().unwrap();
+        if state.losses.len() == LOSS_WINDOW {
+            state.losses.pop_front();
+        }
+        state.losses.push_back(ping.loss());
+        *state.outcomes.entry(ping.outcome.as_str()).or_default() += 1;
+
+        if ping.outcome == Outcome::Success {
+            let rtt = ping.ping / 1000.0;
+            let bucket = RTT_BUCKETS
+                .iter()
+                .position(|&le| rtt <= le)
+                .unwrap_or(RTT_BUCKETS.len());
+            state.buckets[bucket] += 1;
+            state.sum += rtt;
+            state.count += 1;
+        }
+        state.last = Some(ping.clone());
+    }
+}
+
+/// Writer for the metric families
+struct Metrics(String);
+
+impl Metrics {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP ping_log_{name} {help}");
+        let _ = writeln!(self.0, "# TYPE ping_log_{name} {kind}");
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let _ = write!(self.0, "ping_log_{name}");
+        if !labels.is_empty() {
+            self.0.push('{');
+            for (i, (key, value)) in labels.iter().enumerate() {
+                if i > 0 {
+                    self.0.push(',');
+                }
+                let value = value
+                    .replace('\\', r"\\")
+                    .replace('"', r#"\""#)
+                    .replace('\n', r"\n");
+                let _ = write!(self.0, "{key}=\"{value}\"");
+            }
+            self.0.push('}');
+        }
+        let _ = writeln!(self.0, " {value}");
+    }
+}
+
+/// Renders all metrics
+pub fn render(
+    probes: &[Arc<ProbeMetrics>],
+    health: &[Arc<Health>],
+    hw: &hw::Status,
+    mc: &[mc::Status],
+    scheduler: &scheduler::Status,
+) -> String {
+    let mut m = Metrics(String::new());
+    let states: Vec<_> = probes
+        .iter()
+        .map(|p| (p.target.as_str(), p.state.lock().unwrap()))
+        .collect();
+
+    m.family(
+        "up",
+        "gauge",
+        "Whether the last probe of the target succeeded.",
+    );
+    for (target, state) in &states {
+        if let Some(last) = &state.last {
+            let up = (last.outcome == Outcome::Success) as u8;
+            m.sample("up", &[("target", target)], up.into());
+        }
+    }
+    m.family(
+        "last_probe_timestamp_seconds",
+        "gauge",
+        "Time of the last probe of the target.",
+    );
+    for (target, state) in &states {
+        if let Some(last) = &state.last {
+            let time = last.time as f64;
+            m.sample("last_probe_timestamp_seconds", &[("target", target)], time);
+        }
+    }
+    m.family(
+        "last_rtt_seconds",
+        "gauge",
+        "Response time of the last successful probe of the target.",
+    );
+    for (target, state) in &states {
+        if let Some(last) = state
+            .last
+            .as_ref()
+            .filter(|l| l.outcome == Outcome::Success)
+        {
+            m.sample(
+                "last_rtt_seconds",
+                &[("target", target)],
+                last.ping / 1000.0,
+            );
+        }
+    }
+    m.family(
+        "loss_ratio",
+        "gauge",
+        "Fraction of lost requests over the last 60 intervals.",
+    );
+    for (target, state) in &states {
+        if !state.losses.is_empty() {
+            let loss = state.losses.iter().sum::<f64>() / state.losses.len() as f64;
+            m.sample("loss_ratio", &[("target", target)], loss);
+        }
+    }
+    m.family("probes_total", "counter", "Number of probes by outcome.");
+    for (target, state) in &states {
+        for (outcome, count) in &state.outcomes {
+            let labels = [("target", *target), ("outcome", *outcome)];
+            m.sample("probes_total", &labels, *count as f64);
+        }
+    }
+    m.family(
+        "rtt_seconds",
+        "histogram",
+        "Response times of the successful probes.",
+    );
+    for (target, state) in &states {
+        let mut cumulative = 0;
+        for (i, count) in state.buckets.iter().enumerate() {
+            cumulative += count;
+            let le = RTT_BUCKETS.get(i).map_or("+Inf".into(), f64::to_string);
+            let labels = [("target", *target), ("le", &le)];
+            m.sample("rtt_seconds_bucket", &labels, cumulative as f64);
+        }
+        m.sample("rtt_seconds_sum", &[("target", target)], state.sum);
+        m.sample(
+            "rtt_seconds_count",
+            &[("target", target)],
+            state.count as f64,
+        );
+    }
+
+    let health: Vec<_> = health.iter().map(|h| h.status()).collect();
+    m.family(
+        "monitor_healthy",
+        "gauge",
+        "Whether the last tick of the monitor was logged.",
+    );
+    for status in &health {
+        let healthy = status.healthy as u8;
+        m.sample(
+            "monitor_healthy",
+            &[("target", &status.target)],
+            healthy.into(),
+        );
+    }
+    m.family(
+        "monitor_failures_total",
+        "counter",
+        "Number of failed ticks of the monitor.",
+    );
+    for status in &health {
+        let failures = status.failures as f64;
+        m.sample(
+            "monitor_failures_total",
+            &[("target", &status.target)],
+            failures,
+        );
+    }
+    m.family(
+        "monitor_consecutive_failures",
+        "gauge",
+        "Number of failed ticks since the last successful one.",
+    );
+    for status in &health {
+        let failures = status.consecutive_failures as f64;
+        let labels = [("target", status.target.as_str())];
+        m.sample("monitor_consecutive_failures", &labels, failures);
+    }
+    m.family(
+        "monitor_last_success_timestamp_seconds",
+        "gauge",
+        "Time of the last successfully logged tick.",
+    );
+    for status in &health {
+        if let Some(time) = status.last_success {
+            let labels = [("target", status.target.as_str())];
+            m.sample(
+                "monitor_last_success_timestamp_seconds",
+                &labels,
+                time as f64,
+            );
+        }
+    }
+
+    m.family(
+        "scheduler_lag_seconds",
+        "gauge",
+        "Delay of the last tick after its intended time.",
+    );
+    m.sample("scheduler_lag_seconds", &[], scheduler.lag.last / 1000.0);
+    m.family(
+        "scheduler_running_probes",
+        "gauge",
+        "Number of currently running probes.",
+    );
+    m.sample("scheduler_running_probes", &[], scheduler.running as f64);
+    m.family(
+        "log_skipped_lines_total",
+        "counter",
+        "Number of corrupt log lines skipped while reading the logs.",
+    );
+    let skipped = ping_stats::skipped_lines() as f64;
+    m.sample("log_skipped_lines_total", &[], skipped);
+
+    m.family(
+        "load_percent",
+        "gauge",
+        "CPU load times the number of CPUs.",
+    );
+    m.sample("load_percent", &[], hw.load.into());
+    m.family("memory_used_bytes", "gauge", "Used memory.");
+    m.sample("memory_used_bytes", &[], gib(hw.memory_used));
+    m.family("memory_total_bytes", "gauge", "Installed memory.");
+    m.sample("memory_total_bytes", &[], gib(hw.memory_total));
+    m.family("temperature_celsius", "gauge", "CPU temperature.");
+    m.sample("temperature_celsius", &[], hw.temperature.into());
+
+    m.family(
+        "mc_up",
+        "gauge",
+        "Whether the Minecraft server answered the status request.",
+    );
+    for server in mc {
+        m.sample(
+            "mc_up",
+            &[("server", &server.addr)],
+            server.online() as u8 as f64,
+        );
+    }
+    m.family(
+        "mc_players",
+        "gauge",
+        "Number of players on the Minecraft server.",
+    );
+    for server in mc {
+        m.sample(
+            "mc_players",
+            &[("server", &server.addr)],
+            server.players as f64,
+        );
+    }
+    m.family(
+        "mc_max_players",
+        "gauge",
+        "Maximum number of players on the Minecraft server.",
+    );
+    for server in mc {
+        let max = server.max_players as f64;
+        m.sample("mc_max_players", &[("server", &server.addr)], max);
+    }
+
+    m.0
+}
+
+fn gib(value: f32) -> f64 {
+    value as f64 * (1u64 << 30) as f64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::ping::Burst;
+
+    #[test]
+    fn probes() {
+        let probe = Arc::new(ProbeMetrics::new("tcp://\"x\":1".into()));
+        probe.record(&Ping::new(100, 12.0));
+        probe.record(&Ping::lost(160, Outcome::Timeout));
+        probe.record(&Ping {
+            burst: Some(Burst::new(2, &[2000.0], 0.0)),
+            ..Ping::new(220, 7000.0)
+        });
+        let health = Arc::new(Health::new("tcp://\"x\":1".into()));
+        health.failure("disk full");
+        let scheduler = scheduler::Scheduler::new(1).status();
+        let hw = hw::Status::default();
+
+        let text = render(&[probe], &[health], &hw, &[], &scheduler);
+        let target = r#"target="tcp://\"x\":1""#;
+        for line in [
+            format!("ping_log_up{{{target}}} 1"),
+            format!("ping_log_last_probe_timestamp_seconds{{{target}}} 220"),
+            format!("ping_log_last_rtt_seconds{{{target}}} 7"),
+            format!("ping_log_loss_ratio{{{target}}} 0.5"),
+            format!("ping_log_probes_total{{{target},outcome=\"timeout\"}} 1"),
+            format!("ping_log_probes_total{{{target},outcome=\"success\"}} 2"),
+            format!("ping_log_rtt_seconds_bucket{{{target},le=\"0.01\"}} 0"),
+            format!("ping_log_rtt_seconds_bucket{{{target},le=\"0.025\"}} 1"),
+            format!("ping_log_rtt_seconds_bucket{{{target},le=\"5\"}} 1"),
+            format!("ping_log_rtt_seconds_bucket{{{target},le=\"+Inf\"}} 2"),
+            format!("ping_log_rtt_seconds_count{{{target}}} 2"),
+            format!("ping_log_monitor_healthy{{{target}}} 0"),
+            format!("ping_log_monitor_failures_total{{{target}}} 1"),
+            "# TYPE ping_log_rtt_seconds histogram".into(),
+            "ping_log_scheduler_running_probes 0".into(),
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "missing '{line}' in\n{text}"
+            );
+        }
+    }
+}
@@ -0,0 +1,362 @@
+//! Detection of outages of the ping targets.
+//!
+//! A run of consecutive lost pings becomes an outage once it reaches the
+//! threshold. It lasts from the first lost to the next successful ping.
+//! The outages are appended to `<log dir>/outages.txt` of each target.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, info, warn};
+
+use super::ping::{Outcome, Ping};
+use super::ping_stats;
+use super::stream::{Hub, Update};
+
+/// Name of the outage history in the log directory of a target
+pub const OUTAGE_FILE: &str = "outages.txt";
+
+/// Consecutive lost pings of a target
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Outage {
+    pub target: String,
+    /// Time of the first lost ping
+    pub start: i64,
+    /// Time of the first successful ping afterwards, `None` while ongoing
+    pub end: Option<i64>,
+    /// Length in seconds, until now if ongoing
+    pub duration: i64,
+    /// Number of lost pings
+    pub lost: usize,
+}
+
+impl Outage {
+    /// Seconds of the outage within the range
+    pub fn overlap(&self, start: i64, end: i64) -> i64 {
+        let until = self.end.unwrap_or(self.start + self.duration);
+        (until.min(end) - self.start.max(start)).max(0)
+    }
+}
+
+impl fmt::Display for Outage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.end {
+            Some(_) => write!(
+                f,
+                "outage of {} ended after {}s ({} lost)",
+                self.target, self.duration, self.lost
+            ),
+            None => write!(f, "outage of {} started", self.target),
+        }
+    }
+}
+
+/// Line of the outage history: `<start> <end|-> <lost>`
+struct Record {
+    start: i64,
+    end: Option<i64>,
+    lost: usize,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.end {
+            Some(end) => write!(f, "{} {end} {}", self.start, self.lost),
+            None => write!(f, "{} - {}", self.start, self.lost),
+        }
+    }
+}
+
+impl FromStr for Record {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut columns = s.split(' ');
+        let start = columns.next().ok_or(())?.parse().map_err(|_| ())?;
+        let end = match columns.next().ok_or(())? {
+            "-" => None,
+            end => Some(end.parse().map_err(|_| ())?),
+        };
+        let lost = columns.next().ok_or(())?.parse().map_err(|_| ())?;
+        Ok(Record { start, end, lost })
+    }
+}
+
+/// Uptime of a target in percent over the last day, week and month
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Uptime {
+    pub target: String,
+    pub day: f64,
+    pub week: f64,
+    pub month: f64,
+}
+
+/// Outages and the current run of lost pings of a target
+#[derive(Default)]
+pub struct History {
+    /// Ordered by the start
+    outages: Vec<Outage>,
+    /// Start and number of the consecutive lost pings
+    run: Option<(i64, usize)>,
+}
+
+impl History {
+    /// Adds the ping and returns the outage if it started or ended, after
+    /// `threshold` consecutive lost pings
+    pub fn add(&mut self, target: &str, ping: &Ping, threshold: usize) -> Option<Outage> {
+        // Restarted monitors say nothing about the target
+        if ping.outcome == Outcome::MonitorFailure {
+            return None;
+        }
+        if ping.loss() >= 1.0 {
+            let (start, lost) = self.run.get_or_insert((ping.time, 0));
+            *lost += 1;
+            let (start, lost) = (*start, *lost);
+            match self.outages.last_mut() {
+                Some(outage) if outage.end.is_none() => {
+                    outage.lost = lost;
+                    outage.duration = ping.time - start;
+                    None
+                }
+                _ if lost >= threshold => {
+                    let outage = Outage {
+                        target: target.into(),
+                        start,
+                        end: None,
+                        duration: ping.time - start,
+                        lost,
+                    };
+                    self.outages.push(outage.clone());
+                    Some(outage)
+                }
+                _ => None,
+            }
+        } else {
+            self.run = None;
+            match self.outages.last_mut() {
+                Some(outage) if outage.end.is_none() => {
+                    outage.end = Some(ping.time);
+                    outage.duration = ping.time - outage.start;
+                    Some(outage.clone())
+                }
+                _ => None,
+            }
+        }
+    }
+
+    /// Returns the outages that overlap the range (0 for unbounded) and last
+    /// at least `min_duration`, beginning with the newest. Ongoing outages
+    /// last until `now`.
+    pub fn outages(&self, start: i64, end: i64, min_duration: i64, now: i64) -> Vec<Outage> {
+        self.outages
+            .iter()
+            .rev()
+            .map(|outage| Outage {
+                duration: outage.end.unwrap_or(now) - outage.start,
+                ..outage.clone()
+            })
+            .filter(|o| end == 0 || o.start < end)
+            .filter(|o| start == 0 || o.start + o.duration > start)
+            .filter(|o| o.duration >= min_duration)
+            .collect()
+    }
+}
+
+/// Turns the lost pings into outages
+pub struct Detector {
+    log_dir: PathBuf,
+    /// Number of consecutive lost pings that are an outage
+    threshold: usize,
+    histories: Mutex<HashMap<String, History>>,
+}
+
+impl Detector {
+    /// Creates a detector and loads the outage history of the targets
+    pub fn new(log_dir: PathBuf, threshold: usize, targets: &[String]) -> Detector {
+        let histories = targets
+            .iter()
+            .map(|target| {
+                let outages = load(&log_dir, target);
+                let run = outages
+                    .last()
+                    .filter(|o| o.end.is_none())
+                    .map(|o| (o.start, o.lost));
+                (target.clone(), History { outages, run })
+            })
+            .collect();
+        Detector {
+            log_dir,
+            threshold: threshold.max(1),
+            histories: Mutex::new(histories),
+        }
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Adds the ping and returns the outage if it started or ended
+    pub fn update(&self, target: &str, ping: &Ping) -> Option<Outage> {
+        let mut histories = self.histories.lock().unwrap();
+        let history = histories.entry(target.into()).or_default();
+        let change = history.add(target, ping, self.threshold);
+        if let Some(outage) = &change {
+            if let Err(e) = self.append(outage) {
+                error!("could not save the outage of {target}: {e}");
+            }
+        }
+        change
+    }
+
+    /// Appends the outage to the history, a later line of the same outage
+    /// replaces the earlier one.
+    fn append(&self, outage: &Outage) -> io::Result<()> {
+        let dir = ping_stats::target_dir(&self.log_dir, &outage.target);
+        fs::create_dir_all(&dir)?;
+        let record = Record {
+            start: outage.start,
+            end: outage.end,
+            lost: outage.lost,
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(OUTAGE_FILE))?;
+        writeln!(file, "{record}")
+    }
+
+    /// Returns the outages of the target like [`History::outages`]
+    pub fn outages(
+        &self,
+        target: &str,
+        start: i64,
+        end: i64,
+        min_duration: i64,
+        now: i64,
+    ) -> Vec<Outage> {
+        let histories = self.histories.lock().unwrap();
+        histories.get(target).map_or_else(Vec::new, |history| {
+            history.outages(start, end, min_duration, now)
+        })
+    }
+
+    /// Returns the uptime of the target over the last day, week and month
+    pub fn uptime(&self, target: &str, now: i64) -> Uptime {
+        let percent = |days: i64| {
+            let start = now - days * 24 * 60 * 60;
+            let down: i64 = self
+                .outages(target, start, now, 0, now)
+                .iter()
+                .map(|o| o.overlap(start, now))
+                .sum();
+            100.0 * (1.0 - down as f64 / (now - start) as f64)
+        };
+        Uptime {
+            target: target.into(),
+            day: percent(1),
+            week: percent(7),
+            month: percent(30),
+        }
+    }
+}
+
+/// Reads the outage history of the target
+fn load(log_dir: &std::path::Path, target: &str) -> Vec<Outage> {
+    let path = ping_stats::target_dir(log_dir, target).join(OUTAGE_FILE);
+    let Ok(input) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let mut outages: Vec<Outage> = Vec::new();
+    for record in input.lines().filter_map(|l| l.parse::<Record>().ok()) {
+        let outage = Outage {
+            target: target.into(),
+            start: record.start,
+            end: record.end,
+            duration: record.end.map_or(0, |end| end - record.start),
+            lost: record.lost,
+        };
+        match outages.iter_mut().rev().find(|o| o.start == record.start) {
+            Some(previous) => *previous = outage,
+            None => outages.push(outage),
+        }
+    }
+    outages.sort_by_key(|o| o.start);
+    outages
+}
+
+/// Detects the outages in the logged pings and publishes their changes
+pub async fn run(detector: Arc<Detector>, hub: Arc<Hub>) {
+    let mut receiver = hub.receiver();
+    loop {
+        let (target, ping) = match receiver.recv().await {
+            Ok(Update::Ping(target, ping)) => (target, ping),
+            Ok(_) => continue,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("outage detection skipped {skipped} updates");
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        if let Some(outage) = detector.update(&target, &ping) {
+            info!("{outage}");
+            hub.publish(Update::Outage(outage));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detection() {
+        let dir = std::env::temp_dir().join(format!("ping-log-outage-{}", std::process::id()));
+        let target = "1.1.1.1".to_string();
+        let detector = Detector::new(dir.clone(), 3, std::slice::from_ref(&target));
+        let lost = |time| Ping::lost(time, Outcome::Timeout);
+
+        // Too short
+        assert_eq!(detector.update(&target, &lost(100)), None);
+        assert_eq!(detector.update(&target, &lost(160)), None);
+        assert_eq!(detector.update(&target, &Ping::new(220, 10.0)), None);
+
+        assert_eq!(detector.update(&target, &lost(280)), None);
+        assert_eq!(detector.update(&target, &lost(340)), None);
+        let started = detector.update(&target, &lost(400)).unwrap();
+        assert_eq!((started.start, started.end, started.lost), (280, None, 3));
+        assert_eq!(
+            detector.update(&target, &Ping::lost(430, Outcome::MonitorFailure)),
+            None
+        );
+        assert_eq!(detector.update(&target, &lost(460)), None);
+        assert_eq!(detector.outages(&target, 0, 0, 0, 500)[0].duration, 220);
+
+        // Survives restarts, without the pings lost since the start was saved
+        let detector = Detector::new(dir.clone(), 3, std::slice::from_ref(&target));
+        let ended = detector.update(&target, &Ping::new(520, 10.0)).unwrap();
+        assert_eq!((ended.start, ended.end), (280, Some(520)));
+        assert_eq!((ended.duration, ended.lost), (240, 3));
+        assert_eq!(detector.update(&target, &Ping::new(580, 10.0)), None);
+
+        let detector = Detector::new(dir.clone(), 3, std::slice::from_ref(&target));
+        assert_eq!(detector.outages(&target, 0, 0, 0, 1000), [ended]);
+        assert!(detector.outages(&target, 600, 0, 0, 1000).is_empty());
+        assert!(detector.outages(&target, 0, 280, 0, 1000).is_empty());
+        assert!(detector.outages(&target, 0, 0, 241, 1000).is_empty());
+        assert!(detector.outages("8.8.8.8", 0, 0, 0, 1000).is_empty());
+
+        let now = 24 * 60 * 60;
+        let uptime = detector.uptime(&target, now);
+        assert_eq!(uptime.day, 100.0 * (1.0 - 240.0 / now as f64));
+        assert!(uptime.week > uptime.day && uptime.month > uptime.week);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
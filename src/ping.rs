@@ -1,18 +1,210 @@
 use std::fmt;
 use std::str::FromStr;
+use std::time::Duration;
 
 use serde::Serialize;
 
+/// Result of a ping request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    #[default]
+    Success,
+    /// No reply within the timeout
+    Timeout,
+    /// The host or network was reported as unreachable
+    Unreachable,
+    /// The connection was refused (TCP)
+    Refused,
+    /// The connection was reset (TCP)
+    Reset,
+    /// The TLS handshake failed
+    TlsFailure,
+    /// The response could not be parsed
+    ProtocolError,
+    /// The response did not match the expectation (e.g. HTTP status)
+    Mismatch,
+    /// The host name could not be resolved
+    ResolveFailure,
+    /// The request could not be started (e.g. missing permissions)
+    SpawnFailure,
+    /// The monitor failed and was restarted (no request was performed)
+    MonitorFailure,
+    /// The outcome is not known (e.g. written by a newer version)
+    Unknown,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Timeout => "timeout",
+            Outcome::Unreachable => "unreachable",
+            Outcome::Refused => "refused",
+            Outcome::Reset => "reset",
+            Outcome::TlsFailure => "tls-failure",
+            Outcome::ProtocolError => "protocol-error",
+            Outcome::Mismatch => "mismatch",
+            Outcome::ResolveFailure => "resolve-failure",
+            Outcome::SpawnFailure => "spawn-failure",
+            Outcome::MonitorFailure => "monitor-failure",
+            Outcome::Unknown => "unknown",
+        }
+    }
+}
+
+impl FromStr for Outcome {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "success" => Ok(Outcome::Success),
+            "timeout" => Ok(Outcome::Timeout),
+            "unreachable" => Ok(Outcome::Unreachable),
+            "refused" => Ok(Outcome::Refused),
+            "reset" => Ok(Outcome::Reset),
+            "tls-failure" => Ok(Outcome::TlsFailure),
+            "protocol-error" => Ok(Outcome::ProtocolError),
+            "mismatch" => Ok(Outcome::Mismatch),
+            "resolve-failure" => Ok(Outcome::ResolveFailure),
+            "spawn-failure" => Ok(Outcome::SpawnFailure),
+            "monitor-failure" => Ok(Outcome::MonitorFailure),
+            "unknown" => Ok(Outcome::Unknown),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Reply to a single probe (e.g. an ICMP echo request)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Reply {
+    pub rtt: Duration,
+    /// Time to live (or hop limit) of the reply, if known
+    pub ttl: Option<u8>,
+    pub seq: u16,
+    pub http: Option<HttpTiming>,
+    pub dns: Option<DnsAnswer>,
+}
+
+/// Response to a DNS query
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct DnsAnswer {
+    /// Response code (e.g. 0 for NOERROR, 3 for NXDOMAIN)
+    pub rcode: u8,
+    /// Number of answer records
+    pub answers: u16,
+    /// Whether the query was retried over TCP
+    pub tcp: bool,
+}
+
+/// Phases of an HTTP request (durations in ms)
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct HttpTiming {
+    /// Response status code
+    pub status: u16,
+    /// Name resolution
+    pub dns: f64,
+    /// TCP handshake
+    pub connect: f64,
+    /// TLS handshake (only for HTTPS)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<f64>,
+    /// Time from sending the request until the first response byte
+    pub ttfb: f64,
+}
+
+/// Statistics of a burst of echo requests (durations in ms)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Burst {
+    pub sent: u16,
+    pub received: u16,
+    pub min: f64,
+    pub max: f64,
+    /// Mean deviation of the response times
+    pub mdev: f64,
+    /// Interarrival jitter as defined by RFC 3550
+    pub jitter: f64,
+}
+
+impl Burst {
+    /// Computes the statistics for the response times of the received replies
+    pub fn new(sent: u16, rtts: &[f64], jitter: f64) -> Burst {
+        let n = rtts.len() as f64;
+        let avg = rtts.iter().sum::<f64>() / n;
+        let sq_avg = rtts.iter().map(|r| r * r).sum::<f64>() / n;
+        Burst {
+            sent,
+            received: rtts.len() as u16,
+            min: rtts.iter().copied().reduce(f64::min).unwrap_or_default(),
+            max: rtts.iter().copied().reduce(f64::max).unwrap_or_default(),
+            mdev: if rtts.is_empty() {
+                0.0
+            } else {
+                (sq_avg - avg * avg).max(0.0).sqrt()
+            },
+            jitter,
+        }
+    }
+}
+
 /// Ping data (timestamp and duration in ms)
+///
+/// The log line format is `<time> <ping>` followed by optional
+/// `key=value` columns. Unknown columns are ignored by the reader.
+/// Old logs without an `outcome` column mark lost pings with a duration of
+/// 1000 ms.
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Ping {
     pub time: i64,
     pub ping: f64,
+    pub outcome: Outcome,
+    /// Time to live (or hop limit) of the echo reply
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u8>,
+    /// Sequence number of the echo request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u16>,
+    /// Statistics if multiple requests were sent, `ping` is their average
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub burst: Option<Burst>,
+    /// Request phases of HTTP probes, `ping` is the total duration
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpTiming>,
+    /// Response of DNS probes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsAnswer>,
 }
 
 impl Ping {
+    /// Successful ping
     pub fn new(time: i64, ping: f64) -> Ping {
-        Ping { time, ping }
+        Ping {
+            time,
+            ping,
+            outcome: Outcome::Success,
+            ttl: None,
+            seq: None,
+            burst: None,
+            http: None,
+            dns: None,
+        }
+    }
+
+    /// Failed ping with the given reason
+    pub fn lost(time: i64, outcome: Outcome) -> Ping {
+        Ping {
+            outcome,
+            ..Ping::new(time, 0.0)
+        }
+    }
+
+    /// Fraction of the lost requests of this interval
+    pub fn loss(&self) -> f64 {
+        match self.burst {
+            Some(burst) if burst.sent > 0 => 1.0 - burst.received as f64 / burst.sent as f64,
+            _ if self.outcome != Outcome::Success => 1.0,
+            _ => 0.0,
+        }
     }
 }
 
@@ -24,7 +216,44 @@ impl From<(i64, f64)> for Ping {
 
 impl fmt::Display for Ping {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} {:.1}", self.time, self.ping)
+        write!(
+            f,
+            "{} {:.1} outcome={}",
+            self.time,
+            self.ping,
+            self.outcome.as_str()
+        )?;
+        if let Some(ttl) = self.ttl {
+            write!(f, " ttl={ttl}")?;
+        }
+        if let Some(seq) = self.seq {
+            write!(f, " seq={seq}")?;
+        }
+        if let Some(b) = &self.burst {
+            write!(
+                f,
+                " sent={} recv={} min={:.1} max={:.1} mdev={:.1} jitter={:.1}",
+                b.sent, b.received, b.min, b.max, b.mdev, b.jitter
+            )?;
+        }
+        if let Some(h) = &self.http {
+            write!(
+                f,
+                " status={} dns={:.1} connect={:.1}",
+                h.status, h.dns, h.connect
+            )?;
+            if let Some(tls) = h.tls {
+                write!(f, " tls={tls:.1}")?;
+            }
+            write!(f, " ttfb={:.1}", h.ttfb)?;
+        }
+        if let Some(d) = &self.dns {
+            write!(f, " rcode={} answers={}", d.rcode, d.answers)?;
+            if d.tcp {
+                write!(f, " tcp=1")?;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -32,10 +261,163 @@ impl FromStr for Ping {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (t, p) = s.split_once(char::is_whitespace).ok_or(())?;
-        Ok(Ping {
-            time: t.parse().map_err(|_| ())?,
-            ping: p.parse().map_err(|_| ())?,
-        })
+        let mut columns = s.split_whitespace();
+        let mut ping = Ping::new(
+            columns.next().ok_or(())?.parse().map_err(|_| ())?,
+            columns.next().ok_or(())?.parse().map_err(|_| ())?,
+        );
+        let mut outcome = None;
+        let mut burst = Burst::new(0, &[], 0.0);
+        let mut http = None;
+        let mut dns = None;
+        for column in columns {
+            let (key, value) = column.split_once('=').ok_or(())?;
+            match key {
+                "outcome" => outcome = Some(value.parse().unwrap_or(Outcome::Unknown)),
+                "ttl" => ping.ttl = Some(value.parse().map_err(|_| ())?),
+                "seq" => ping.seq = Some(value.parse().map_err(|_| ())?),
+                "sent" => burst.sent = value.parse().map_err(|_| ())?,
+                "recv" => burst.received = value.parse().map_err(|_| ())?,
+                "min" => burst.min = value.parse().map_err(|_| ())?,
+                "max" => burst.max = value.parse().map_err(|_| ())?,
+                "mdev" => burst.mdev = value.parse().map_err(|_| ())?,
+                "jitter" => burst.jitter = value.parse().map_err(|_| ())?,
+                "status" => {
+                    http.get_or_insert(HttpTiming::default()).status =
+                        value.parse().map_err(|_| ())?
+                }
+                "dns" => {
+                    http.get_or_insert(HttpTiming::default()).dns = value.parse().map_err(|_| ())?
+                }
+                "connect" => {
+                    http.get_or_insert(HttpTiming::default()).connect =
+                        value.parse().map_err(|_| ())?
+                }
+                "tls" => {
+                    http.get_or_insert(HttpTiming::default()).tls =
+                        Some(value.parse().map_err(|_| ())?)
+                }
+                "rcode" => {
+                    dns.get_or_insert(DnsAnswer::default()).rcode = value.parse().map_err(|_| ())?
+                }
+                "answers" => {
+                    dns.get_or_insert(DnsAnswer::default()).answers =
+                        value.parse().map_err(|_| ())?
+                }
+                "tcp" => dns.get_or_insert(DnsAnswer::default()).tcp = value == "1",
+                "ttfb" => {
+                    http.get_or_insert(HttpTiming::default()).ttfb =
+                        value.parse().map_err(|_| ())?
+                }
+                _ => {}
+            }
+        }
+        if burst.sent > 0 {
+            ping.burst = Some(burst);
+        }
+        ping.http = http;
+        ping.dns = dns;
+        ping.outcome = match outcome {
+            Some(outcome) => outcome,
+            None if ping.ping >= 1000.0 => Outcome::Timeout,
+            None => Outcome::Success,
+        };
+        Ok(ping)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn burst() {
+        let burst = Burst::new(3, &[10.0, 20.0, 30.0], 0.0);
+        assert_eq!(burst.received, 3);
+        assert_eq!(burst.min, 10.0);
+        assert_eq!(burst.max, 30.0);
+        assert!((burst.mdev - 8.165).abs() < 0.001);
+
+        let lost = Burst::new(3, &[], 0.0);
+        assert_eq!(lost.received, 0);
+        assert_eq!(lost.min, 0.0);
+        assert_eq!(lost.mdev, 0.0);
+    }
+
+    #[test]
+    fn line_format() {
+        let ping = Ping {
+            ttl: Some(57),
+            seq: Some(3),
+            ..Ping::new(1626457680, 11.5)
+        };
+        let line = "1626457680 11.5 outcome=success ttl=57 seq=3";
+        assert_eq!(ping.to_string(), line);
+        assert_eq!(line.parse(), Ok(ping));
+
+        let lost = Ping::lost(1626457680, Outcome::ResolveFailure);
+        assert_eq!(lost.to_string(), "1626457680 0.0 outcome=resolve-failure");
+        assert_eq!(lost.to_string().parse(), Ok(lost));
+
+        // Slow but successful pings are not lost
+        let slow = Ping::new(1626457680, 1200.0);
+        assert_eq!(slow.to_string().parse(), Ok(slow));
+
+        let burst = Ping {
+            burst: Some(Burst::new(5, &[10.0, 12.0, 14.0, 16.0], 1.5)),
+            ..Ping::new(1626457680, 13.0)
+        };
+        let line =
+            "1626457680 13.0 outcome=success sent=5 recv=4 min=10.0 max=16.0 mdev=2.2 jitter=1.5";
+        assert_eq!(burst.to_string(), line);
+        let parsed: Ping = line.parse().unwrap();
+        assert_eq!(parsed.burst.unwrap().received, 4);
+        assert_eq!(parsed.burst.unwrap().mdev, 2.2);
+
+        let http = Ping {
+            http: Some(HttpTiming {
+                status: 200,
+                dns: 1.5,
+                connect: 10.2,
+                tls: Some(20.1),
+                ttfb: 30.0,
+            }),
+            ..Ping::new(1626457680, 62.0)
+        };
+        let line =
+            "1626457680 62.0 outcome=success status=200 dns=1.5 connect=10.2 tls=20.1 ttfb=30.0";
+        assert_eq!(http.to_string(), line);
+        assert_eq!(line.parse(), Ok(http));
+
+        let dns = Ping {
+            dns: Some(DnsAnswer {
+                rcode: 3,
+                answers: 0,
+                tcp: true,
+            }),
+            ..Ping::new(1626457680, 8.5)
+        };
+        let line = "1626457680 8.5 outcome=success rcode=3 answers=0 tcp=1";
+        assert_eq!(dns.to_string(), line);
+        assert_eq!(line.parse(), Ok(dns));
+
+        assert_eq!("1626457680 11.5".parse(), Ok(Ping::new(1626457680, 11.5)));
+        assert_eq!(
+            "1626457680 1000".parse(),
+            Ok(Ping {
+                outcome: Outcome::Timeout,
+                ..Ping::new(1626457680, 1000.0)
+            })
+        );
+        assert_eq!(
+            "1626457680 11.5 future=1".parse(),
+            Ok(Ping::new(1626457680, 11.5))
+        );
+        assert_eq!(
+            "1626457680 0.0 outcome=x".parse(),
+            Ok(Ping::lost(1626457680, Outcome::Unknown))
+        );
+        assert_eq!("1626457680".parse::<Ping>(), Err(()));
+        assert_eq!("1626457680 11.5 ttl=x".parse::<Ping>(), Err(()));
     }
 }
@@ -0,0 +1,500 @@
+//! Aggregation of the logged pings into time buckets (e.g. hourly statistics)
+//! and response time distributions.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use chrono::{DateTime, FixedOffset, Local, LocalResult, NaiveDateTime, TimeDelta, TimeZone};
+use chrono_tz::Tz;
+use serde::Serialize;
+
+use super::ping::{Outcome, Ping};
+
+/// Time zone the buckets are aligned to (e.g. hours or days)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    /// Time zone of the server
+    Local,
+    /// Fixed offset from UTC
+    Fixed(FixedOffset),
+    /// IANA time zone with its daylight saving time (e.g. `Europe/Berlin`)
+    Named(Tz),
+}
+
+impl FromStr for Zone {
+    type Err = String;
+
+    /// Parses `local`, `UTC`, an offset like `+02:00` or an IANA name
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("local") {
+            Ok(Zone::Local)
+        } else if s.eq_ignore_ascii_case("utc") || s == "Z" {
+            Ok(Zone::UTC)
+        } else if let Ok(offset) = s.parse() {
+            Ok(Zone::Fixed(offset))
+        } else {
+            s.parse()
+                .map(Zone::Named)
+                .map_err(|_| format!("invalid time zone '{s}'"))
+        }
+    }
+}
+
+impl Zone {
+    pub const UTC: Zone = Zone::Fixed(FixedOffset::east_opt(0).unwrap());
+
+    /// Returns the local time of the timestamp, `None` if it is out of range
+    pub fn local(&self, time: i64) -> Option<NaiveDateTime> {
+        let utc = DateTime::from_timestamp(time, 0)?;
+        Some(match self {
+            Zone::Local => utc.with_timezone(&Local).naive_local(),
+            Zone::Fixed(offset) => utc.with_timezone(offset).naive_local(),
+            Zone::Named(tz) => utc.with_timezone(tz).naive_local(),
+        })
+    }
+
+    /// Returns the timestamp of the local time
+    pub fn timestamp(&self, local: NaiveDateTime) -> i64 {
+        fn convert<Tz: TimeZone>(tz: &Tz, local: NaiveDateTime) -> i64 {
+            match tz.from_local_datetime(&local) {
+                LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => t.timestamp(),
+                // Skipped by a daylight saving transition, use the offset before it
+                LocalResult::None => tz
+                    .from_local_datetime(&(local + TimeDelta::hours(1)))
+                    .earliest()
+                    .map_or(local.and_utc().timestamp(), |t| t.timestamp()),
+            }
+        }
+        match self {
+            Zone::Local => convert(&Local, local),
+            Zone::Fixed(offset) => convert(offset, local),
+            Zone::Named(tz) => convert(tz, local),
+        }
+    }
+}
+
+/// Statistics of the pings of a time bucket (durations in ms)
+///
+/// The response time statistics are missing if no ping succeeded.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Bucket {
+    /// Begin of the bucket
+    pub time: i64,
+    /// Number of pings
+    pub count: usize,
+    /// Average fraction of lost requests
+    pub loss: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avg: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p50: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p90: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p95: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p99: Option<f64>,
+}
+
+/// Collects the pings of a bucket
+#[derive(Debug, Default)]
+pub struct Summary {
+    count: usize,
+    lost: f64,
+    rtts: Vec<f64>,
+}
+
+impl Summary {
+    pub fn add(&mut self, ping: &Ping) {
+        self.count += 1;
+        self.lost += ping.loss();
+        if ping.outcome == Outcome::Success {
+            self.rtts.push(ping.ping);
+        }
+    }
+
+    /// Response times of the successful pings in ascending order
+    pub fn sorted_rtts(&mut self) -> &[f64] {
+        self.rtts.sort_unstable_by(f64::total_cmp);
+        &self.rtts
+    }
+
+    pub fn bucket(&mut self, time: i64) -> Bucket {
+        let count = self.count;
+        let loss = if count > 0 {
+            round(self.lost / count as f64)
+        } else {
+            0.0
+        };
+        let rtts = self.sorted_rtts();
+        let avg = (!rtts.is_empty()).then(|| round(rtts.iter().sum::<f64>() / rtts.len() as f64));
+        Bucket {
+            time,
+            count,
+            loss,
+            min: rtts.first().copied(),
+            avg,
+            max: rtts.last().copied(),
+            p50: percentile(rtts, 50.0),
+            p90: percentile(rtts, 90.0),
+            p95: percentile(rtts, 95.0),
+            p99: percentile(rtts, 99.0),
+        }
+    }
+}
+
+/// Nearest-rank percentile of the sorted values
+pub fn percentile(sorted: &[f64], p: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+fn round(value: f64) -> f64 {
+    (value * 1000.0).round() / 1000.0
+}
+
+/// Maximum number of buckets that are grouped
+pub const MAX_BUCKETS: i64 = 10_000;
+
+/// Returns the number of buckets of `width` seconds between `start` and
+/// `end`, `None` if the width is not positive or the range overflows.
+pub fn bucket_count(start: i64, end: i64, width: i64) -> Option<i64> {
+    if width <= 0 {
+        return None;
+    }
+    Some(end.checked_sub(start)?.checked_add(width - 1)? / width)
+}
+
+/// Aggregates the pings between `start` (inclusive) and `end` (exclusive)
+/// into buckets of `width` seconds, aligned to the local time of the zone.
+///
+/// Buckets without pings are included with a count of 0.
+pub fn aggregate(
+    pings: impl IntoIterator<Item = Ping>,
+    start: i64,
+    end: i64,
+    width: i64,
+    zone: Zone,
+) -> Vec<Bucket> {
+    let pings = pings.into_iter().map(|p| (p.time, p));
+    group(pings, start, end, width, zone, |s: &mut Summary, p| {
+        s.add(&p)
+    })
+    .into_iter()
+    .map(|(time, mut summary)| summary.bucket(time))
+    .collect()
+}
+
+/// Groups the timestamped items between `start` (inclusive) and `end`
+/// (exclusive) into buckets of `width` seconds, aligned to the local time of
+/// the zone, and returns the begin of the buckets with their summaries.
+///
+/// At most [`MAX_BUCKETS`] buckets are created, none if the range is out of
+/// the supported time range.
+pub fn group<T, S: Default>(
+    items: impl IntoIterator<Item = (i64, T)>,
+    start: i64,
+    end: i64,
+    width: i64,
+    zone: Zone,
+    mut add: impl FnMut(&mut S, T),
+) -> Vec<(i64, S)> {
+    let width = TimeDelta::seconds(width.max(1));
+    let floor = |local: NaiveDateTime| {
+        let secs = local.and_utc().timestamp();
+        let secs = secs - secs.rem_euclid(width.num_seconds());
+        DateTime::from_timestamp(secs, 0)
+            .unwrap_or_default()
+            .naive_utc()
+    };
+
+    let mut buckets = BTreeMap::<NaiveDateTime, S>::new();
+    let (Some(first), Some(_)) = (zone.local(start), zone.local(end)) else {
+        return Vec::new();
+    };
+    // The alignment can add a bucket before the start
+    let count = bucket_count(start, end, width.num_seconds()).unwrap_or(0) + 1;
+    let mut key = floor(first);
+    for _ in 0..count.min(MAX_BUCKETS) {
+        if zone.timestamp(key) >= end {
+            break;
+        }
+        buckets.entry(key).or_default();
+        key += width;
+    }
+    for (time, item) in items {
+        if !(start..end).contains(&time) {
+            continue;
+        }
+        if let Some(local) = zone.local(time) {
+            add(buckets.entry(floor(local)).or_default(), item);
+        }
+    }
+
+    buckets
+        .into_iter()
+        .map(|(key, summary)| (zone.timestamp(key), summary))
+        .collect()
+}
+
+/// Bins of the response time histogram
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bins {
+    pub count: usize,
+    /// Lower edge of the first bin, defaults to the minimum response time
+    pub min: Option<f64>,
+    /// Upper edge of the last bin, defaults to the maximum response time
+    pub max: Option<f64>,
+}
+
+/// Histogram of the response times (in ms)
+///
+/// Bin `i` covers `[min + i * width, min + (i + 1) * width)`, the last bin
+/// also includes `max`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Histogram {
+    pub min: f64,
+    pub max: f64,
+    pub width: f64,
+    pub counts: Vec<usize>,
+    /// Number of response times below `min`
+    pub below: usize,
+    /// Number of response times above `max`
+    pub above: usize,
+}
+
+impl Histogram {
+    pub fn new(rtts: &[f64], bins: Bins) -> Histogram {
+        let min = rtts.iter().copied().reduce(f64::min);
+        let max = rtts.iter().copied().reduce(f64::max);
+        let mut histogram = Histogram::empty(bins, min, max);
+        for &rtt in rtts {
+            histogram.add(rtt, 1);
+        }
+        histogram
+    }
+
+    /// Creates a histogram without values, the range defaults to the
+    /// minimum and maximum of the values that are added later
+    pub fn empty(bins: Bins, min: Option<f64>, max: Option<f64>) -> Histogram {
+        let count = bins.count.max(1);
+        let min = bins.min.or(min).unwrap_or_default();
+        let max = bins.max.or(max).unwrap_or_default();
+        // Equal response times are put into a single (1 ms wide) range
+        let max = if max > min { max } else { min + 1.0 };
+        Histogram {
+            min,
+            max,
+            width: (max - min) / count as f64,
+            counts: vec![0; count],
+            below: 0,
+            above: 0,
+        }
+    }
+
+    /// Adds `count` times the response time `rtt`
+    pub fn add(&mut self, rtt: f64, count: usize) {
+        if rtt < self.min {
+            self.below += count;
+        } else if rtt > self.max {
+            self.above += count;
+        } else {
+            let bin = ((rtt - self.min) / self.width) as usize;
+            let last = self.counts.len() - 1;
+            self.counts[bin.min(last)] += count;
+        }
+    }
+}
+
+/// Response time distribution of a time range
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Distribution {
+    #[serde(flatten)]
+    pub stats: Bucket,
+    pub histogram: Histogram,
+}
+
+/// Computes the statistics and histogram of the pings between `start`
+/// (inclusive) and `end` (exclusive).
+pub fn distribution(
+    pings: impl IntoIterator<Item = Ping>,
+    start: i64,
+    end: i64,
+    bins: Bins,
+) -> Distribution {
+    let mut summary = Summary::default();
+    for ping in pings {
+        if (start..end).contains(&ping.time) {
+            summary.add(&ping);
+        }
+    }
+    Distribution {
+        stats: summary.bucket(start),
+        histogram: Histogram::new(summary.sorted_rtts(), bins),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::ping::Burst;
+
+    #[test]
+    fn zone() {
+        assert_eq!("local".parse(), Ok(Zone::Local));
+        let utc = Zone::UTC;
+        assert_eq!("UTC".parse(), Ok(utc));
+        assert_eq!("Z".parse(), Ok(utc));
+        let cest = Zone::Fixed(FixedOffset::east_opt(2 * 3600).unwrap());
+        assert_eq!("+02:00".parse(), Ok(cest));
+        assert!("Mars/Olympus".parse::<Zone>().is_err());
+        let berlin: Zone = "Europe/Berlin".parse().unwrap();
+        assert_eq!(berlin, Zone::Named(chrono_tz::Europe::Berlin));
+
+        let time = 1626457680;
+        let local = |zone: Zone, time| zone.local(time).unwrap();
+        assert_eq!(cest.timestamp(local(cest, time)), time);
+        assert_eq!(local(cest, time) - local(utc, time), TimeDelta::hours(2));
+        // Summer and winter time
+        assert_eq!(local(berlin, time), local(cest, time));
+        assert_eq!(
+            local(berlin, 1767225600) - local(utc, 1767225600),
+            TimeDelta::hours(1)
+        );
+        assert_eq!(utc.local(9_000_000_000_000), None);
+    }
+
+    #[test]
+    fn percentiles() {
+        let values: Vec<f64> = (1..=100).map(f64::from).collect();
+        assert_eq!(percentile(&values, 50.0), Some(50.0));
+        assert_eq!(percentile(&values, 99.0), Some(99.0));
+        assert_eq!(percentile(&values, 100.0), Some(100.0));
+        assert_eq!(percentile(&values, 0.0), Some(1.0));
+        assert_eq!(percentile(&[7.0], 90.0), Some(7.0));
+        assert_eq!(percentile(&[], 90.0), None);
+    }
+
+    #[test]
+    fn buckets() {
+        let zone: Zone = "+01:00".parse().unwrap();
+        // 2021-07-16 17:00 in UTC+1
+        let start = 1626451200;
+        let pings = vec![
+            Ping::new(start + 7260, 30.0),
+            Ping::lost(start + 7200, Outcome::Timeout),
+            Ping {
+                burst: Some(Burst::new(4, &[9.0, 11.0, 10.0], 0.0)),
+                ..Ping::new(start + 60, 10.0)
+            },
+            Ping::new(start, 20.0),
+            // Outside of the range
+            Ping::new(start - 60, 99.0),
+            Ping::new(start + 3 * 3600, 99.0),
+        ];
+        assert_eq!(bucket_count(start, start + 3 * 3600, 3600), Some(3));
+        assert_eq!(bucket_count(i64::MIN, i64::MAX, 60), None);
+        assert_eq!(bucket_count(0, i64::MAX, 60), None);
+        assert_eq!(bucket_count(start, start + 60, 0), None);
+
+        let buckets = aggregate(pings, start, start + 3 * 3600, 3600, zone);
+        assert_eq!(buckets.len(), 3);
+        assert_eq!(
+            buckets[0],
+            Bucket {
+                time: start,
+                count: 2,
+                loss: 0.125,
+                min: Some(10.0),
+                avg: Some(15.0),
+                max: Some(20.0),
+                p50: Some(10.0),
+                p90: Some(20.0),
+                p95: Some(20.0),
+                p99: Some(20.0),
+            }
+        );
+        assert_eq!(
+            (buckets[1].time, buckets[1].count, buckets[1].avg),
+            (start + 3600, 0, None)
+        );
+        assert_eq!((buckets[2].count, buckets[2].loss), (2, 0.5));
+        assert_eq!(buckets[2].avg, Some(30.0));
+
+        // The day of the switch to summer time has 23 hours
+        let berlin = Zone::Named(chrono_tz::Europe::Berlin);
+        // 2026-03-29 00:00 in Berlin
+        let day = 1774738800;
+        let buckets = aggregate(Vec::new(), day, day + 2 * 86400, 86400, berlin);
+        let times: Vec<_> = buckets.iter().map(|b| b.time).collect();
+        assert_eq!(times, [day, day + 23 * 3600, day + 47 * 3600]);
+
+        // Days begin at midnight of the zone
+        let buckets = aggregate(Vec::new(), start, start + 3600, 86400, zone);
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].time, start - 17 * 3600);
+
+        // Out of the supported time range
+        let far = 9_000_000_000_000;
+        assert!(aggregate(Vec::new(), far, far + 3600, 60, zone).is_empty());
+        let buckets = aggregate(Vec::new(), 0, start, 1, zone);
+        assert_eq!(buckets.len(), MAX_BUCKETS as usize);
+    }
+
+    #[test]
+    fn histogram() {
+        let rtts = [1.0, 2.0, 2.5, 4.0, 9.0, 10.0];
+        let auto = Bins {
+            count: 3,
+            min: None,
+            max: None,
+        };
+        let histogram = Histogram::new(&rtts, auto);
+        assert_eq!(
+            (histogram.min, histogram.max, histogram.width),
+            (1.0, 10.0, 3.0)
+        );
+        assert_eq!(histogram.counts, [3, 1, 2]);
+        assert_eq!((histogram.below, histogram.above), (0, 0));
+
+        let fixed = Bins {
+            count: 4,
+            min: Some(2.0),
+            max: Some(6.0),
+        };
+        let histogram = Histogram::new(&rtts, fixed);
+        assert_eq!(histogram.counts, [2, 0, 1, 0]);
+        assert_eq!((histogram.below, histogram.above), (1, 2));
+
+        let histogram = Histogram::new(&[5.0, 5.0], auto);
+        assert_eq!((histogram.min, histogram.max), (5.0, 6.0));
+        assert_eq!(histogram.counts, [2, 0, 0]);
+        assert_eq!(Histogram::new(&[], auto).counts, [0, 0, 0]);
+    }
+
+    #[test]
+    fn range_distribution() {
+        let pings = (0..100).map(|i| Ping::new(1000 - i, f64::from(i as i32 + 1)));
+        let bins = Bins {
+            count: 10,
+            min: Some(0.0),
+            max: Some(100.0),
+        };
+        // 1 ms to 91 ms
+        let distribution = distribution(pings, 910, 1001, bins);
+        assert_eq!(distribution.stats.count, 91);
+        assert_eq!(distribution.stats.p50, Some(46.0));
+        assert_eq!(distribution.stats.p99, Some(91.0));
+        assert_eq!(distribution.histogram.counts[0], 9);
+        assert_eq!(distribution.histogram.counts[9], 2);
+        assert_eq!(distribution.histogram.counts.iter().sum::<usize>(), 91);
+    }
+}
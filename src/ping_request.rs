@@ -1,107 +1,467 @@
-use std::fs::OpenOptions;
-use std::fs::{read_dir, remove_file};
-use std::io::{Result, Write};
-use std::path::Path;
-use std::process::Command;
-use std::sync::LazyLock;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::future::Future;
+use std::io::{self, ErrorKind, Result};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
 
 use chrono::Local;
+use clap::ValueEnum;
 use regex::Regex;
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tracing::{error, warn};
 
-use super::ping::Ping;
+use super::health::Health;
+#[cfg(target_os = "linux")]
+use super::icmp;
+use super::metrics::ProbeMetrics;
+use super::ping::{Burst, Outcome, Ping, Reply};
+use super::scheduler::Scheduler;
+use super::storage::Storage;
+use super::stream::{Hub, Update};
+use super::target::Target;
+use super::trace::{self, Reason};
+use super::{dns, http};
 
-pub async fn monitor(host: &str, interval: u64, log_dir: &Path) {
+/// Time to wait for an echo reply
+const TIMEOUT: Duration = Duration::from_secs(1);
+/// Time to wait for the complete response of an HTTP request
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time to wait for the response to a DNS query
+const DNS_TIMEOUT: Duration = Duration::from_secs(2);
+/// Delay before the first restart of a failed monitor
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+/// Maximum delay between restarts of a failing monitor
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Implementation used for the ping requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// In-process ICMP echo requests (Linux only)
+    #[cfg(target_os = "linux")]
+    Native,
+    /// Spawn the system's `ping` command
+    Command,
+}
+
+impl Default for Backend {
+    #[cfg(target_os = "linux")]
+    fn default() -> Self {
+        Backend::Native
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn default() -> Self {
+        Backend::Command
+    }
+}
+
+/// Configuration of the ping monitor
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    /// Time between the ping requests in seconds
+    pub interval: u64,
+    pub backend: Backend,
+    /// Number of echo requests sent per interval
+    pub count: u16,
+    /// Time between the echo requests of an interval
+    pub spacing: Duration,
+    /// Path traces on loss or latency spikes, if enabled
+    pub trace: Option<TraceSettings>,
+}
+
+/// Configuration of the automatic path traces
+#[derive(Debug, Clone, Copy)]
+pub struct TraceSettings {
+    /// Response time in ms above which the path is traced
+    pub threshold: Option<f64>,
+    /// Minimum time between two traces of a target
+    pub cooldown: Duration,
+}
+
+/// State shared by the monitors of all targets
+#[derive(Clone)]
+pub struct Shared {
+    pub scheduler: Arc<Scheduler>,
+    pub storage: Arc<dyn Storage>,
+    pub hub: Arc<Hub>,
+}
+
+/// Replies or failures of the probes of an interval
+type Replies = Vec<std::result::Result<Reply, Outcome>>;
+
+/// Runs the monitor of the target and restarts it if it fails.
+///
+/// The restarts are delayed exponentially as long as the monitor fails
+/// without logging a single tick. Each restart is logged as failed tick.
+pub async fn supervise(
+    target: Target,
+    log_dir: PathBuf,
+    settings: Settings,
+    shared: Shared,
+    health: Arc<Health>,
+    metrics: Arc<ProbeMetrics>,
+) {
+    let mut backoff = MIN_BACKOFF;
     loop {
-        let epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-        let next = Duration::from_secs(((epoch.as_secs() + interval) / interval) * interval);
+        let last_success = health.status().last_success;
+        let task = {
+            let (target, log_dir) = (target.clone(), log_dir.clone());
+            let (shared, health, metrics) = (shared.clone(), health.clone(), metrics.clone());
+            tokio::spawn(async move {
+                monitor(&target, &log_dir, settings, &shared, &health, &metrics).await
+            })
+        };
+        match task.await {
+            Ok(e) => error!("monitor of {target} failed: {e}"),
+            Err(e) => {
+                error!("monitor of {target} crashed: {e}");
+                health.failure(format!("monitor crashed: {e}"));
+            }
+        }
 
-        tokio::time::sleep(next - epoch).await;
+        if health.status().last_success != last_success {
+            backoff = MIN_BACKOFF;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
 
-        let log = perform_request(host).await;
-        write_request(log_dir, log).expect("write log error");
+        let log = Ping::lost(Local::now().timestamp(), Outcome::MonitorFailure);
+        match shared.storage.append(&target.to_string(), &log) {
+            Ok(()) => shared.hub.publish(Update::Ping(target.to_string(), log)),
+            Err(e) => warn!("could not log the failure of {target}: {e}"),
+        }
     }
 }
 
-async fn perform_request(host: &str) -> Ping {
-    #[cfg(not(target_os = "macos"))]
-    const WAIT_ARG: &str = "-w 1";
-    #[cfg(target_os = "macos")]
-    const WAIT_ARG: &str = "-W 1";
+/// Probes the target every interval and logs the results.
+///
+/// Only returns if a result could not be logged.
+async fn monitor(
+    target: &Target,
+    log_dir: &Path,
+    settings: Settings,
+    shared: &Shared,
+    health: &Health,
+    metrics: &ProbeMetrics,
+) -> io::Error {
+    let scheduler = &shared.scheduler;
+    let mut seq = 0u16;
+    let mut jitter = Jitter::default();
+    let mut last_trace: Option<Instant> = None;
+    loop {
+        scheduler.tick(settings.interval).await;
+
+        let time = Local::now().timestamp();
+        let replies = match (target, settings.backend) {
+            #[cfg(target_os = "linux")]
+            (Target::Icmp(host), Backend::Native) => {
+                perform_echo(scheduler, host, seq, &settings).await
+            }
+            (Target::Icmp(host), Backend::Command) => {
+                perform_request(scheduler, host, &settings).await
+            }
+            (Target::Tcp { host, port }, _) => {
+                perform_connect(scheduler, host, *port, seq, &settings).await
+            }
+            (Target::Http(target), _) => {
+                perform(scheduler, seq, &settings, HTTP_TIMEOUT, |seq| {
+                    http::request(target, seq, HTTP_TIMEOUT)
+                })
+                .await
+            }
+            (Target::Dns(target), _) => {
+                perform(scheduler, seq, &settings, DNS_TIMEOUT, |seq| {
+                    dns::request(target, seq, DNS_TIMEOUT)
+                })
+                .await
+            }
+        };
+        seq = seq.wrapping_add(settings.count);
 
-    const COUNT_ARG: &str = "-c 1";
+        let log = summarize(time, settings.count, &replies, &mut jitter);
+        metrics.record(&log);
 
-    let time = Local::now().timestamp();
-    let output = Command::new("ping")
-        .args([COUNT_ARG, WAIT_ARG, host])
-        .output()
-        .expect("failed to execute bash 'ping' command");
+        if let Some(trace) = settings.trace {
+            let cooled_down = last_trace.is_none_or(|t| t.elapsed() >= trace.cooldown);
+            if let Some(reason) = Reason::of(&log, trace.threshold).filter(|_| cooled_down) {
+                last_trace = Some(Instant::now());
+                let host = target.host().to_owned();
+                let log_dir = log_dir.to_owned();
+                tokio::spawn(async move { trace::snapshot(&host, &log_dir, time, reason).await });
+            }
+        }
 
-    if !output.stderr.is_empty() {
-        eprintln!(
-            "bash 'ping' error: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
+        if let Err(e) = shared.storage.append(&target.to_string(), &log) {
+            health.failure(format!("could not write log: {e}"));
+            return e;
+        }
+        health.success(time);
+        shared.hub.publish(Update::Ping(target.to_string(), log));
     }
+}
 
-    Ping::new(
-        time,
-        String::from_utf8(output.stdout).map_or(1000.0, |o| parse(&o)),
-    )
+/// Running interarrival jitter estimate (RFC 3550, section 6.4.1)
+#[derive(Debug, Default)]
+struct Jitter {
+    last: Option<f64>,
+    value: f64,
 }
 
-fn parse(input: &str) -> f64 {
-    static PING_RE: LazyLock<Regex> = LazyLock::new(|| {
-        Regex::new(
-            r#"^64 bytes from [\w\.\-:]+( \([\w\.\-:]+\))?: icmp_seq=\d+ ttl=\d+ time=([\d.]+) ms"#,
-        )
-        .unwrap()
-    });
+impl Jitter {
+    fn update(&mut self, rtt: f64) {
+        if let Some(last) = self.last.replace(rtt) {
+            self.value += ((rtt - last).abs() - self.value) / 16.0;
+        }
+    }
+}
+
+/// Combines the replies of an interval into a single ping
+fn summarize(
+    time: i64,
+    count: u16,
+    replies: &[std::result::Result<Reply, Outcome>],
+    jitter: &mut Jitter,
+) -> Ping {
+    let rtts: Vec<f64> = replies
+        .iter()
+        .filter_map(|r| r.as_ref().ok())
+        .map(|r| (r.rtt.as_secs_f64() * 10000.0).round() / 10.0)
+        .collect();
+    for rtt in &rtts {
+        jitter.update(*rtt);
+    }
+
+    let last_reply = replies.iter().rev().find_map(|r| r.as_ref().ok());
+    let mut ping = match last_reply {
+        Some(reply) => Ping {
+            ttl: reply.ttl,
+            seq: Some(reply.seq),
+            http: reply.http,
+            dns: reply.dns,
+            ..Ping::new(time, rtts.iter().sum::<f64>() / rtts.len() as f64)
+        },
+        None => {
+            let outcome = replies
+                .iter()
+                .rev()
+                .find_map(|r| r.err())
+                .unwrap_or(Outcome::Timeout);
+            Ping::lost(time, outcome)
+        }
+    };
+    if count > 1 {
+        ping.burst = Some(Burst::new(count, &rtts, jitter.value));
+    }
+    ping
+}
+
+/// Performs the native ICMP echo requests of an interval
+#[cfg(target_os = "linux")]
+async fn perform_echo(scheduler: &Scheduler, host: &str, seq: u16, settings: &Settings) -> Replies {
+    let addr = match resolve(host).await {
+        Ok(addr) => addr,
+        Err(e) => {
+            warn!("could not resolve {host}: {e}");
+            return vec![Err(Outcome::ResolveFailure)];
+        }
+    };
+    perform(scheduler, seq, settings, TIMEOUT, |seq| echo(addr, seq)).await
+}
+
+/// Sends a single echo request
+#[cfg(target_os = "linux")]
+async fn echo(addr: IpAddr, seq: u16) -> std::result::Result<Reply, Outcome> {
+    let result = match icmp::Socket::new(addr.is_ipv6()) {
+        Ok(socket) => socket.echo(addr, seq, TIMEOUT).await,
+        Err(e) => Err(e),
+    };
+    result.map_err(|e| match e.kind() {
+        ErrorKind::TimedOut => Outcome::Timeout,
+        ErrorKind::HostUnreachable | ErrorKind::NetworkUnreachable => Outcome::Unreachable,
+        _ => {
+            warn!("echo request failed: {e}");
+            Outcome::SpawnFailure
+        }
+    })
+}
 
-    if let Some((_, input)) = input.split_once('\n') {
-        if let Some(found) = PING_RE.captures(input) {
-            return found[2].parse().unwrap_or(1000.0);
+/// Measures the duration of TCP handshakes
+async fn perform_connect(
+    scheduler: &Scheduler,
+    host: &str,
+    port: u16,
+    seq: u16,
+    settings: &Settings,
+) -> Replies {
+    let addr = match resolve(host).await {
+        Ok(addr) => SocketAddr::new(addr, port),
+        Err(e) => {
+            warn!("could not resolve {host}: {e}");
+            return vec![Err(Outcome::ResolveFailure)];
         }
+    };
+    perform(scheduler, seq, settings, TIMEOUT, |seq| connect(addr, seq)).await
+}
+
+async fn connect(addr: SocketAddr, seq: u16) -> std::result::Result<Reply, Outcome> {
+    let start = Instant::now();
+    match TcpStream::connect(addr).await {
+        Ok(_) => Ok(Reply {
+            rtt: start.elapsed(),
+            seq,
+            ..Default::default()
+        }),
+        Err(e) => Err(match e.kind() {
+            ErrorKind::ConnectionRefused => Outcome::Refused,
+            ErrorKind::ConnectionReset => Outcome::Reset,
+            ErrorKind::TimedOut => Outcome::Timeout,
+            ErrorKind::HostUnreachable | ErrorKind::NetworkUnreachable => Outcome::Unreachable,
+            _ => {
+                warn!("tcp connect failed: {e}");
+                Outcome::SpawnFailure
+            }
+        }),
     }
-    1000.0
 }
 
-fn write_request(dir: &Path, log: Ping) -> Result<()> {
-    if !dir.exists() {
-        std::fs::create_dir_all(dir).expect("Error creating log dir");
+/// Performs the probes of an interval through the scheduler
+///
+/// Probes that do not finish within `timeout` are timed out.
+async fn perform<F, Fut>(
+    scheduler: &Scheduler,
+    seq: u16,
+    settings: &Settings,
+    timeout: Duration,
+    probe: F,
+) -> Replies
+where
+    F: Fn(u16) -> Fut,
+    Fut: Future<Output = std::result::Result<Reply, Outcome>>,
+{
+    let mut replies = Vec::with_capacity(settings.count as usize);
+    for i in 0..settings.count {
+        if i > 0 {
+            tokio::time::sleep(settings.spacing).await;
+        }
+        let reply = scheduler.run(timeout, probe(seq.wrapping_add(i))).await;
+        replies.push(reply.unwrap_or(Err(Outcome::Timeout)));
     }
+    replies
+}
+
+/// Resolves the host name, preferring IPv4 addresses
+pub async fn resolve(host: &str) -> Result<IpAddr> {
+    let addrs: Vec<_> = tokio::net::lookup_host((host, 0))
+        .await?
+        .map(|a| a.ip())
+        .collect();
+    addrs
+        .iter()
+        .find(|a| a.is_ipv4())
+        .or(addrs.first())
+        .copied()
+        .ok_or(ErrorKind::AddrNotAvailable.into())
+}
+
+/// Performs the ping requests of an interval with the system's `ping` command
+async fn perform_request(scheduler: &Scheduler, host: &str, settings: &Settings) -> Replies {
+    let (deadline, args) = ping_args(host, settings);
+    let command = Command::new("ping").args(args).kill_on_drop(true).output();
+    // Give the command some time to start and report its results
+    let output = match scheduler.run(deadline + TIMEOUT, command).await {
+        Some(Ok(output)) => output,
+        Some(Err(e)) => {
+            warn!("failed to execute 'ping' command: {e}");
+            return vec![Err(Outcome::SpawnFailure)];
+        }
+        None => {
+            warn!("'ping' command did not finish in time");
+            return vec![Err(Outcome::Timeout)];
+        }
+    };
 
-    let filename = Local::now().format("%y%m%d.txt").to_string();
-    let path = dir.join(filename);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stderr.is_empty() {
+        warn!("'ping' error: {stderr}");
+    }
 
-    if !path.exists() {
-        remove_old_logs(dir);
+    let replies = parse(&stdout);
+    if replies.is_empty() {
+        return vec![Err(failure(&stdout, &stderr))];
     }
+    replies
+        .into_iter()
+        .map(|(seq, ttl, rtt)| {
+            Ok(Reply {
+                rtt: Duration::from_secs_f64(rtt / 1000.0),
+                ttl: Some(ttl),
+                seq,
+                ..Default::default()
+            })
+        })
+        .collect()
+}
 
-    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
-    writeln!(file, "{log}")?;
-    Ok(())
+/// Returns the deadline and arguments of the `ping` command
+fn ping_args(host: &str, settings: &Settings) -> (Duration, [String; 7]) {
+    #[cfg(not(target_os = "macos"))]
+    const WAIT_ARG: &str = "-w";
+    #[cfg(target_os = "macos")]
+    const WAIT_ARG: &str = "-W";
+
+    // The deadline only accepts whole seconds, round up to wait for the last reply
+    let deadline = TIMEOUT + settings.spacing * (settings.count as u32 - 1);
+    let deadline = Duration::from_secs(deadline.as_secs_f64().ceil() as u64);
+    let args = [
+        "-c".into(),
+        settings.count.to_string(),
+        "-i".into(),
+        settings.spacing.as_secs_f64().to_string(),
+        WAIT_ARG.into(),
+        deadline.as_secs().to_string(),
+        host.into(),
+    ];
+    (deadline, args)
 }
 
-fn remove_old_logs(dir: &Path) {
-    let oldest = Local::now() - chrono::Duration::weeks(8);
-    let oldest = oldest.format("%y%m%d").to_string();
+/// Parses the sequence numbers, ttls and response times of the `ping` output
+fn parse(input: &str) -> Vec<(u16, u8, f64)> {
+    static PING_RE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(
+            r#"^\d+ bytes from [\w\.\-:]+( \([\w\.\-:]+\))?: icmp_seq=(\d+) ttl=(\d+) time=([\d.]+) ms"#,
+        )
+        .unwrap()
+    });
 
-    if let Ok(entries) = read_dir(dir) {
-        entries.for_each(|entry| {
-            if let Ok(entry) = entry {
-                let filename = entry.file_name().to_string_lossy().into_owned();
-                if older(&filename, &oldest) {
-                    remove_file(entry.path()).expect("Could not remove old log");
-                }
-            }
+    input
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let found = PING_RE.captures(line)?;
+            Some((
+                found[2].parse().ok()?,
+                found[3].parse().ok()?,
+                found[4].parse().ok()?,
+            ))
         })
-    }
+        .collect()
 }
 
-/// Is the given `filename` older than the formatted date `oldest`
-fn older(filename: &str, oldest: &str) -> bool {
-    filename.len() == 10 && filename.ends_with(".txt") && filename < oldest
+/// Determines the reason of a failed `ping` command
+fn failure(stdout: &str, stderr: &str) -> Outcome {
+    if stderr.contains("unknown host")
+        || stderr.contains("not known")
+        || stderr.contains("Temporary failure in name resolution")
+    {
+        Outcome::ResolveFailure
+    } else if stdout.contains("Unreachable") || stderr.contains("unreachable") {
+        Outcome::Unreachable
+    } else {
+        Outcome::Timeout
+    }
 }
 
 #[cfg(test)]
@@ -121,7 +481,7 @@ PING 1.1.1.1 (1.1.1.1) 56(84) bytes of data.
 1 packets transmitted, 1 received, 0% packet loss, time 0ms
 rtt min/avg/max/mdev = 11.315/11.315/11.315/0.000 ms\n"
             ),
-            11.3
+            vec![(1, 57, 11.3)]
         );
         assert_eq!(
             parse("\
@@ -131,23 +491,167 @@ PING google.com(fra07s29-in-x200e.1e100.net (2a00:1450:4001:802::200e)) 56 data
 --- google.com ping statistics ---
 1 packets transmitted, 1 received, 0% packet loss, time 0ms
 rtt min/avg/max/mdev = 15.877/15.877/15.877/0.000 ms\n"),
-            15.9
+            vec![(1, 118, 15.9)]
+        );
+        assert_eq!(
+            parse(
+                "\
+PING 10.0.0.1 (10.0.0.1) 56(84) bytes of data.
+
+--- 10.0.0.1 ping statistics ---
+1 packets transmitted, 0 received, 100% packet loss, time 0ms\n"
+            ),
+            vec![]
+        );
+        assert_eq!(
+            parse(
+                "\
+PING 1.1.1.1 (1.1.1.1) 56(84) bytes of data.
+64 bytes from 1.1.1.1: icmp_seq=1 ttl=57 time=11.3 ms
+64 bytes from 1.1.1.1: icmp_seq=3 ttl=57 time=12.1 ms
+
+--- 1.1.1.1 ping statistics ---
+3 packets transmitted, 2 received, 33.3333% packet loss, time 400ms\n"
+            ),
+            vec![(1, 57, 11.3), (3, 57, 12.1)]
         );
     }
 
     #[test]
-    fn old_filename() {
+    fn summarize_burst() {
         use super::*;
 
-        assert_eq!(10, "191129.txt".len());
+        let reply = |seq, ms| {
+            Ok(Reply {
+                rtt: Duration::from_millis(ms),
+                ttl: Some(57),
+                seq,
+                ..Default::default()
+            })
+        };
+
+        let mut jitter = Jitter::default();
+        let ping = summarize(
+            100,
+            4,
+            &[
+                reply(0, 10),
+                Err(Outcome::Timeout),
+                reply(2, 14),
+                reply(3, 12),
+            ],
+            &mut jitter,
+        );
+        assert_eq!(ping.outcome, Outcome::Success);
+        assert_eq!(ping.ping, 12.0);
+        assert_eq!(ping.seq, Some(3));
+        let burst = ping.burst.unwrap();
+        assert_eq!((burst.sent, burst.received), (4, 3));
+        assert_eq!((burst.min, burst.max), (10.0, 14.0));
+        assert_eq!(burst.jitter, (4.0 / 16.0) + (2.0 - 4.0 / 16.0) / 16.0);
 
-        assert!(!older("malformed", "191129"));
+        let ping = summarize(160, 2, &[Err(Outcome::Unreachable)], &mut jitter);
+        assert_eq!(ping.outcome, Outcome::Unreachable);
+        assert_eq!(ping.burst.unwrap().received, 0);
 
-        assert!(older("191029.txt", "191129"));
-        assert!(older("191129.txt", "191130"));
+        // Single requests do not store burst statistics
+        let ping = summarize(220, 1, &[reply(4, 11)], &mut jitter);
+        assert_eq!(
+            ping,
+            Ping {
+                ttl: Some(57),
+                seq: Some(4),
+                ..Ping::new(220, 11.0)
+            }
+        );
+    }
 
-        assert!(!older("191129.txt", "191129"));
-        assert!(!older("191129.txt", "191128"));
+    #[test]
+    fn command_args() {
+        use super::*;
+
+        let settings = Settings {
+            interval: 60,
+            backend: Backend::Command,
+            count: 5,
+            spacing: Duration::from_millis(200),
+            trace: None,
+        };
+        // 1.8 s are rounded up
+        let (deadline, args) = ping_args("1.1.1.1", &settings);
+        assert_eq!(deadline, Duration::from_secs(2));
+        assert_eq!(args[..4], ["-c", "5", "-i", "0.2"]);
+        assert_eq!(args[5..], ["2", "1.1.1.1"]);
+
+        let (deadline, args) = ping_args(
+            "1.1.1.1",
+            &Settings {
+                count: 1,
+                ..settings
+            },
+        );
+        assert_eq!((deadline, args[5].as_str()), (TIMEOUT, "1"));
+    }
+
+    #[tokio::test]
+    async fn connect() {
+        use super::*;
+
+        let settings = Settings {
+            interval: 60,
+            backend: Backend::Command,
+            count: 2,
+            spacing: Duration::from_millis(10),
+            trace: None,
+        };
+
+        let scheduler = Scheduler::new(1);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let replies = perform_connect(&scheduler, "127.0.0.1", port, 5, &settings).await;
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0].unwrap().seq, 5);
+        assert_eq!(replies[1].unwrap().seq, 6);
+
+        drop(listener);
+        let replies = perform_connect(&scheduler, "127.0.0.1", port, 0, &settings).await;
+        assert_eq!(replies, vec![Err(Outcome::Refused), Err(Outcome::Refused)]);
+    }
+
+    #[test]
+    fn failure() {
+        use super::*;
+
+        assert_eq!(
+            failure("", "ping: nohost: Name or service not known\n"),
+            Outcome::ResolveFailure
+        );
+        assert_eq!(
+            failure(
+                "From 10.0.0.2 icmp_seq=1 Destination Host Unreachable\n",
+                ""
+            ),
+            Outcome::Unreachable
+        );
+        assert_eq!(
+            failure("1 packets transmitted, 0 received\n", ""),
+            Outcome::Timeout
+        );
+    }
+
+    #[tokio::test]
+    async fn resolve_literal() {
+        use super::*;
+
+        assert_eq!(
+            resolve("127.0.0.1").await.unwrap(),
+            IpAddr::from([127, 0, 0, 1])
+        );
+        assert_eq!(
+            resolve("::1").await.unwrap(),
+            IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1])
+        );
     }
 
     #[test]
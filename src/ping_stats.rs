@@ -1,9 +1,77 @@
-use tracing::error;
+use chrono::{NaiveDate, NaiveTime, Utc};
+use tracing::{error, warn};
 
 use super::ping::Ping;
 
-use std::fs::{self, read_dir};
-use std::path::Path;
+use std::fs::{self, read_dir, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Maximum time between a ping and writing it to the log, which might put it
+/// into the file of the next day
+const WRITE_DELAY: i64 = 60 * 60;
+/// Maximum time the pings of a log file might be off its day, as legacy logs
+/// are named by the local day of the server (UTC-12 to UTC+14)
+const MARGIN: i64 = 14 * 60 * 60 + WRITE_DELAY;
+
+/// Number of corrupt lines that have been skipped while reading the logs
+static SKIPPED_LINES: AtomicU64 = AtomicU64::new(0);
+
+pub fn skipped_lines() -> u64 {
+    SKIPPED_LINES.load(Ordering::Relaxed)
+}
+
+/// Returns the log subdirectory of the given ping target
+///
+/// Characters that are not allowed in filenames (and leading dots) are
+/// replaced by `_`.
+pub fn target_dir(log_dir: &Path, target: &str) -> PathBuf {
+    let name: String = target
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            if c.is_ascii_alphanumeric() || c == '-' || (c == '.' && i > 0) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    log_dir.join(name)
+}
+
+/// Moves the logs of older versions from the log directory itself into the
+/// directory of the target and returns their number.
+///
+/// Logs of the same day are merged, beginning with the older pings.
+pub fn adopt_legacy_logs(log_dir: &Path, target: &str) -> io::Result<usize> {
+    let files = log_files(log_dir);
+    if files.is_empty() {
+        return Ok(0);
+    }
+    let dir = target_dir(log_dir, target);
+    fs::create_dir_all(&dir)?;
+    for name in &files {
+        let legacy = log_dir.join(name);
+        let path = dir.join(name);
+        if path.exists() {
+            let mut content = fs::read(&legacy)?;
+            if !content.is_empty() && !content.ends_with(b"\n") {
+                content.push(b'\n');
+            }
+            content.extend(fs::read(&path)?);
+            let temp = path.with_extension("tmp");
+            fs::write(&temp, content)?;
+            fs::rename(&temp, &path)?;
+            fs::remove_file(&legacy)?;
+        } else {
+            fs::rename(&legacy, &path)?;
+        }
+    }
+    Ok(files.len())
+}
 
 fn is_log_file(name: &str) -> bool {
     name.len() == 10 && name.ends_with(".txt") && name[0..6].chars().all(char::is_numeric)
@@ -23,9 +91,53 @@ pub fn log_files(dir: &Path) -> Vec<String> {
     }
 }
 
+/// Returns the begin of the day (in UTC) of a log file
+pub fn log_day(name: &str) -> Option<i64> {
+    let date = NaiveDate::parse_from_str(name.get(..6)?, "%y%m%d").ok()?;
+    Some(date.and_time(NaiveTime::MIN).and_utc().timestamp())
+}
+
+/// Returns the range of the pings a log file might contain
+pub fn log_range(name: &str) -> Option<(i64, i64)> {
+    let day = log_day(name)?;
+    Some((day - MARGIN, day + 24 * 60 * 60 + MARGIN))
+}
+
+/// Appends the ping to the log file of the current day (in UTC)
+pub fn write_log(dir: &Path, log: &Ping) -> io::Result<()> {
+    if !dir.exists() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let filename = Utc::now().format("%y%m%d.txt").to_string();
+    append_log(&dir.join(filename), log)
+}
+
+/// Appends the ping to the log file, after completing a partial last line
+/// (e.g. of a power cut)
+fn append_log(path: &Path, log: &Ping) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)?;
+    let len = file.metadata()?.len();
+    let mut last = [b'\n'];
+    if len > 0 {
+        file.read_exact_at(&mut last, len - 1)?;
+    }
+    if last[0] != b'\n' {
+        writeln!(file)?;
+    }
+    writeln!(file, "{log}")?;
+    Ok(())
+}
+
 /// Parses the log files and returns the pings for the given range
 /// As the output is reversed and begins with the newest timestamp,
-/// `start` has to be larger (after) than `end`.
+/// `start` has to be larger (after) than `end`, otherwise the range is empty.
+///
+/// Only the log files whose day overlaps the range are read.
 pub fn read_log<P: AsRef<Path>>(
     log_dir: P,
     offset: usize,
@@ -33,60 +145,301 @@ pub fn read_log<P: AsRef<Path>>(
     start: i64,
     end: i64,
 ) -> Vec<Ping> {
-    assert!(start >= end);
-
-    read_log_all(log_dir.as_ref())
-        .skip_while(|ping| start != 0 && ping.time >= start)
-        .skip(offset)
-        .take(count)
-        .take_while(|ping| end == 0 || ping.time >= end)
-        .collect()
-}
+    if start != 0 && start < end {
+        return Vec::new();
+    }
+
+    let log_dir = log_dir.as_ref();
+    let mut result = Vec::new();
+    let mut offset = offset;
+    for name in log_files(log_dir).iter().rev() {
+        if result.len() >= count {
+            break;
+        }
+        let Some((first, last)) = log_range(name) else {
+            continue;
+        };
+        if start != 0 && first >= start {
+            continue; // newer
+        }
+        if end != 0 && last <= end {
+            break; // this and all remaining files are older
+        }
 
-/// Returns an iterator over the past pings from the log files
-fn read_log_all(log_dir: &Path) -> impl Iterator<Item = Ping> {
-    let log_dir_buf = log_dir.to_owned();
-    let mut files = log_files(log_dir);
-    files.reverse();
-    files
-        .into_iter()
-        .flat_map(move |file| read_log_file(&log_dir_buf, Path::new(&file)).into_iter())
+        let pings = read_range(&log_dir.join(name), start, end);
+        let skipped = offset.min(pings.len());
+        offset -= skipped;
+        let remaining = count - result.len();
+        result.extend(pings.into_iter().skip(skipped).take(remaining));
+    }
+    result
 }
 
 /// Parses the logfile and returns the pings
-fn read_log_file(log_dir: &Path, file: &Path) -> Vec<Ping> {
-    let filename = log_dir.join(file);
-    if let Ok(input) = fs::read_to_string(&filename) {
-        parse(&input)
-    } else {
-        error!("Error opening file {:?}\n", filename);
-        Vec::new()
-    }
+pub fn read_log_file(log_dir: &Path, file: &Path) -> Vec<Ping> {
+    read_range(&log_dir.join(file), 0, 0)
 }
 
-fn parse(input: &str) -> Vec<Ping> {
-    let mut result = input
-        .lines()
-        .map(str::parse)
-        .collect::<Result<Vec<Ping>, ()>>()
-        .unwrap_or_default();
+/// Streams the logfile and returns the pings before `start` and at or after
+/// `end` (0 for unbounded), beginning with the newest
+///
+/// Corrupt lines (e.g. half-written on a power cut) are skipped.
+fn read_range(path: &Path, start: i64, end: i64) -> Vec<Ping> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => {
+            error!("Error opening file {:?}\n", path);
+            return Vec::new();
+        }
+    };
+    let mut result = Vec::new();
+    let mut skipped = 0;
+    for line in lines(BufReader::new(file)) {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Error reading file {path:?}: {e}");
+                break;
+            }
+        };
+        let Some(ping) = parse_line(&line) else {
+            skipped += 1;
+            continue;
+        };
+        // The pings are logged in chronological order
+        if start != 0 && ping.time >= start.saturating_add(WRITE_DELAY) {
+            break;
+        }
+        if (start == 0 || ping.time < start) && (end == 0 || ping.time >= end) {
+            result.push(ping);
+        }
+    }
+    if skipped > 0 {
+        warn!("skipped {skipped} corrupt lines in {path:?}");
+        SKIPPED_LINES.fetch_add(skipped, Ordering::Relaxed);
+    }
     result.reverse();
     result
 }
 
+/// Returns the raw lines of a log file, including the line break
+pub fn lines(mut reader: impl BufRead) -> impl Iterator<Item = io::Result<Vec<u8>>> {
+    std::iter::from_fn(move || {
+        let mut line = Vec::new();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => None,
+            Ok(_) => Some(Ok(line)),
+            Err(e) => Some(Err(e)),
+        }
+    })
+}
+
+/// Parses a line of a log file, `None` if it is corrupt
+pub fn parse_line(line: &[u8]) -> Option<Ping> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    std::str::from_utf8(line).ok()?.parse().ok()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::ping::Outcome;
+
+    use chrono::DateTime;
+
+    #[test]
+    fn test_target_dir() {
+        let log = Path::new("log");
+        assert_eq!(target_dir(log, "1.1.1.1"), log.join("1.1.1.1"));
+        assert_eq!(target_dir(log, "::1"), log.join("__1"));
+        assert_eq!(target_dir(log, "../etc"), log.join("_._etc"));
+        assert_eq!(target_dir(log, ".."), log.join("_."));
+        assert_eq!(target_dir(log, "my-host.lan"), log.join("my-host.lan"));
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ping-log-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn partial_line() {
+        let dir = temp_dir("partial");
+        let path = dir.join("210716.txt");
+        fs::write(&path, "1626462540 1").unwrap();
+        append_log(&path, &Ping::new(1626462600, 2.0)).unwrap();
+        assert_eq!(
+            read_range(&path, 0, 0),
+            [Ping::new(1626462600, 2.0), Ping::new(1626462540, 1.0)]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
 
     #[test]
     fn test_parse() {
+        let dir = temp_dir("parse");
+        let path = dir.join("210716.txt");
+        fs::write(&path, "1626457680 11.5\n1626457740 1000\n1626462480 13.9\n").unwrap();
+        assert_eq!(
+            read_range(&path, 0, 0),
+            vec![
+                Ping::new(1626462480, 13.9),
+                Ping {
+                    outcome: Outcome::Timeout,
+                    ..Ping::new(1626457740, 1000.0)
+                },
+                Ping::new(1626457680, 11.5),
+            ]
+        );
+        assert_eq!(
+            read_range(&path, 1626462480, 1626457740),
+            vec![Ping {
+                outcome: Outcome::Timeout,
+                ..Ping::new(1626457740, 1000.0)
+            }]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_lines() {
+        let dir = temp_dir("corrupt");
+        let path = dir.join("210716.txt");
+        let mut input = b"1626457680 11.5\n1626457\0\0\0\n\xff\xfe 1\n\n".to_vec();
+        input.extend(b"1626457740 12 ttl=x\n1626462480 13.9\n1626462540 1");
+        fs::write(&path, input).unwrap();
+
+        let skipped = skipped_lines();
         assert_eq!(
-            parse("1626457680 11.5\n1626457740 1000\n1626462480 13.9\n"),
+            read_range(&path, 0, 0),
             vec![
+                Ping::new(1626462540, 1.0),
                 Ping::new(1626462480, 13.9),
-                Ping::new(1626457740, 1000.0),
                 Ping::new(1626457680, 11.5),
             ]
         );
+        assert!(skipped_lines() >= skipped + 4);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ranges() {
+        let dir = temp_dir("ranges");
+        // Three days with a ping every six hours
+        let days = ["260101", "260102", "260103"];
+        let mut times = Vec::new();
+        for name in days {
+            let day = log_day(name).unwrap();
+            let lines: String = (0..4)
+                .map(|i| format!("{} 1\n", day + i * 6 * 3600))
+                .collect();
+            fs::write(dir.join(format!("{name}.txt")), lines).unwrap();
+            times.extend((0..4).map(|i| day + i * 6 * 3600));
+        }
+        // Not a log file
+        fs::write(dir.join("notes.txt"), "invalid").unwrap();
+        times.reverse();
+
+        let read = |offset, count, start, end| -> Vec<i64> {
+            read_log(&dir, offset, count, start, end)
+                .into_iter()
+                .map(|p| p.time)
+                .collect()
+        };
+        assert_eq!(read(0, usize::MAX, 0, 0), times);
+        assert_eq!(read(2, 3, 0, 0), times[2..5]);
+        // The second day and the first ping of the third
+        let second = log_day(days[1]).unwrap();
+        let third = log_day(days[2]).unwrap();
+        assert_eq!(read(0, usize::MAX, third + 1, second), times[3..8]);
+        assert_eq!(read(1, 2, third + 1, second), times[4..6]);
+        assert_eq!(read(0, usize::MAX, second, 0), times[8..]);
+        assert!(read(0, usize::MAX, 100, 50).is_empty());
+        assert!(read(0, usize::MAX, second, third).is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Benchmark over eight weeks of one-second data
+    /// (`cargo test --release -- --ignored --nocapture bench`)
+    #[test]
+    #[ignore]
+    fn bench_read_log() {
+        use std::time::Instant;
+
+        let dir = temp_dir("bench");
+        let first = log_day("260101").unwrap();
+        let days: Vec<_> = (0..56)
+            .map(|i| DateTime::from_timestamp(first + i * 86400, 0).unwrap())
+            .map(|t| t.format("%y%m%d").to_string())
+            .collect();
+        let instant = Instant::now();
+        for name in &days {
+            let day = log_day(name).unwrap();
+            let mut lines = String::new();
+            for time in day..day + 86400 {
+                lines += &format!("{time} {:.2}\n", 10.0 + (time % 97) as f64 / 10.0);
+            }
+            fs::write(dir.join(format!("{name}.txt")), lines).unwrap();
+        }
+        println!("generated {} days in {:?}", days.len(), instant.elapsed());
+
+        let newest = log_day(&days[55]).unwrap() + 86400;
+        let seventh_week = log_day(&days[7]).unwrap();
+        let cases = [
+            ("newest 1000 pings", 0, 1000, 0, 0),
+            ("last hour", 0, usize::MAX, newest, newest - 3600),
+            (
+                "day seven weeks ago",
+                0,
+                usize::MAX,
+                seventh_week + 86400,
+                seventh_week,
+            ),
+            (
+                "hour seven weeks ago",
+                0,
+                usize::MAX,
+                seventh_week + 3600,
+                seventh_week,
+            ),
+        ];
+        for (name, offset, count, start, end) in cases {
+            let instant = Instant::now();
+            let pings = read_log(&dir, offset, count, start, end);
+            println!("{name}: {} pings in {:?}", pings.len(), instant.elapsed());
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn legacy_logs() {
+        let dir = temp_dir("legacy");
+        fs::write(dir.join("260101.txt"), "100 1\n").unwrap();
+        fs::write(dir.join("260102.txt"), "200 1").unwrap();
+        let target = target_dir(&dir, "1.1.1.1");
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("260102.txt"), "300 1\n").unwrap();
+
+        assert_eq!(adopt_legacy_logs(&dir, "1.1.1.1").unwrap(), 2);
+        assert!(log_files(&dir).is_empty());
+        let times: Vec<_> = read_log(&target, 0, usize::MAX, 0, 0)
+            .into_iter()
+            .map(|p| p.time)
+            .collect();
+        assert_eq!(times, [300, 200, 100]);
+        assert_eq!(adopt_legacy_logs(&dir, "1.1.1.1").unwrap(), 0);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn write_failure() {
+        // The log dir cannot be created below a regular file
+        let file = std::env::temp_dir().join(format!("ping-log-file-{}", std::process::id()));
+        std::fs::write(&file, "").unwrap();
+        assert!(write_log(&file.join("target"), &Ping::new(0, 1.0)).is_err());
+        std::fs::remove_file(&file).unwrap();
     }
 }
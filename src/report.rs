@@ -0,0 +1,498 @@
+//! Monthly availability (SLA) reports of the targets.
+//!
+//! The availability is the time outside of outages (see [super::outage]),
+//! which are detected again from the stored pings. The latency budget is
+//! checked against the successful pings.
+
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+use chrono::{Datelike, NaiveDate};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use super::outage::History;
+use super::ping::{Outcome, Ping};
+use super::ping_aggregate::Zone;
+use super::storage::Storage;
+
+/// Pings are read in chunks of this many seconds
+const CHUNK: i64 = 24 * 60 * 60;
+/// Maximum number of months of a report
+pub const MAX_MONTHS: usize = 36;
+
+/// Calendar month like `2026-09`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Month {
+    pub year: i32,
+    pub month: u32,
+}
+
+impl Month {
+    /// Returns the month of the timestamp in the zone, `None` if it is out
+    /// of range
+    pub fn of(time: i64, zone: &Zone) -> Option<Month> {
+        let local = zone.local(time)?;
+        Some(Month {
+            year: local.year(),
+            month: local.month(),
+        })
+    }
+
+    pub fn next(self) -> Month {
+        if self.month == 12 {
+            Month {
+                year: self.year + 1,
+                month: 1,
+            }
+        } else {
+            Month {
+                month: self.month + 1,
+                ..self
+            }
+        }
+    }
+
+    /// Returns the timestamp of the first midnight of the month in the zone
+    pub fn start(self, zone: &Zone) -> i64 {
+        let date = NaiveDate::from_ymd_opt(self.year, self.month, 1).unwrap_or_default();
+        zone.timestamp(date.and_hms_opt(0, 0, 0).unwrap())
+    }
+}
+
+/// Returns whether `from` is not after `to` and the report of the range has
+/// at most [`MAX_MONTHS`]
+pub fn valid_range(from: Month, to: Month) -> bool {
+    let months = (to.year - from.year) as i64 * 12 + to.month as i64 - from.month as i64;
+    (0..MAX_MONTHS as i64).contains(&months)
+}
+
+impl fmt::Display for Month {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}", self.year, self.month)
+    }
+}
+
+impl FromStr for Month {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let date = NaiveDate::parse_from_str(&format!("{s}-01"), "%Y-%m-%d")
+            .map_err(|_| format!("expected <year>-<month>, got '{s}'"))?;
+        Ok(Month {
+            year: date.year(),
+            month: date.month(),
+        })
+    }
+}
+
+/// Promised service level
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Budget {
+    /// Minimum availability in percent
+    pub availability: f64,
+    /// Maximum response time in ms
+    pub latency: f64,
+}
+
+/// Service level of a target in a month
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MonthReport {
+    pub month: String,
+    /// Begin of the month
+    pub start: i64,
+    /// Begin of the next month
+    pub end: i64,
+    /// Number of pings
+    pub samples: usize,
+    /// Number of lost pings
+    pub lost: usize,
+    /// Time outside of outages in percent of the monitored time
+    pub availability: Option<f64>,
+    pub availability_met: Option<bool>,
+    /// Number of outages that started in this month
+    pub outages: usize,
+    /// Total length of the outages within this month in seconds
+    pub downtime: i64,
+    /// Mean time to repair of the ended outages in seconds
+    pub mttr: Option<f64>,
+    /// Successful pings slower than the latency budget in percent
+    pub over_budget: Option<f64>,
+}
+
+/// Monthly service levels of a target
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    pub target: String,
+    pub budget: Budget,
+    pub months: Vec<MonthReport>,
+}
+
+/// Output format of the report subcommand
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Markdown,
+    Html,
+}
+
+/// Counts of the pings of a month
+#[derive(Default)]
+struct Counts {
+    samples: usize,
+    lost: usize,
+    /// Successful pings over the latency budget
+    over: usize,
+    /// Time of the first ping
+    first: Option<i64>,
+}
+
+/// Generates the report of the target for the months `from` to `to`
+/// (inclusive), with outages of at least `threshold` consecutive lost pings.
+pub fn generate(
+    storage: &dyn Storage,
+    target: &str,
+    (from, to): (Month, Month),
+    zone: &Zone,
+    budget: Budget,
+    threshold: usize,
+    now: i64,
+) -> Report {
+    let mut months = Vec::new();
+    let mut month = from;
+    while month <= to {
+        months.push((month, month.start(zone), month.next().start(zone)));
+        month = month.next();
+    }
+
+    // Detected like by the outage detector, so both agree
+    let mut history = History::default();
+    let mut counts: Vec<Counts> = months.iter().map(|_| Counts::default()).collect();
+    for ((_, start, end), counts) in months.iter().zip(&mut counts) {
+        let end = (*end).min(now);
+        for chunk in (*start..end).step_by(CHUNK as usize) {
+            let pings = storage.query(target, 0, usize::MAX, (chunk + CHUNK).min(end), chunk);
+            for ping in pings.iter().rev() {
+                // Restarted monitors say nothing about the target
+                if ping.outcome == Outcome::MonitorFailure {
+                    continue;
+                }
+                add(counts, ping, budget);
+                history.add(target, ping, threshold);
+            }
+        }
+    }
+    // Ongoing outages last until now
+    let outages = history.outages(0, 0, 0, now);
+
+    let months = months
+        .iter()
+        .zip(counts)
+        .map(|(&(month, start, end), counts)| {
+            let monitored = counts.first.map(|first| (first.max(start), end.min(now)));
+            let downtime = monitored.map_or(0, |(start, end)| {
+                outages.iter().map(|o| o.overlap(start, end)).sum()
+            });
+            let started: Vec<_> = outages
+                .iter()
+                .filter(|o| (start..end).contains(&o.start))
+                .collect();
+            let repairs: Vec<i64> = started
+                .iter()
+                .filter(|o| o.end.is_some())
+                .map(|o| o.duration)
+                .collect();
+            let availability = monitored
+                .filter(|(start, end)| start < end)
+                .map(|(start, end)| 100.0 * (1.0 - downtime as f64 / (end - start) as f64));
+            let successful = counts.samples - counts.lost;
+            MonthReport {
+                month: month.to_string(),
+                start,
+                end,
+                samples: counts.samples,
+                lost: counts.lost,
+                availability,
+                availability_met: availability.map(|a| a >= budget.availability),
+                outages: started.len(),
+                downtime,
+                mttr: (!repairs.is_empty())
+                    .then(|| repairs.iter().sum::<i64>() as f64 / repairs.len() as f64),
+                over_budget: (successful > 0)
+                    .then(|| 100.0 * counts.over as f64 / successful as f64),
+            }
+        })
+        .collect();
+    Report {
+        target: target.into(),
+        budget,
+        months,
+    }
+}
+
+fn add(counts: &mut Counts, ping: &Ping, budget: Budget) {
+    counts.samples += 1;
+    counts.first.get_or_insert(ping.time);
+    if ping.loss() >= 1.0 {
+        counts.lost += 1;
+    } else if ping.ping > budget.latency {
+        counts.over += 1;
+    }
+}
+
+/// Renders the reports as standalone document
+pub fn render(reports: &[Report], format: Format) -> String {
+    match format {
+        Format::Markdown => markdown(reports),
+        Format::Html => html(reports),
+    }
+}
+
+/// Columns of a month in the rendered reports
+fn columns(month: &MonthReport) -> [String; 7] {
+    let percent = |p: Option<f64>| p.map_or("-".into(), |p| format!("{p:.3}%"));
+    let met = match month.availability_met {
+        Some(true) => " (met)",
+        Some(false) => " (missed)",
+        None => "",
+    };
+    [
+        month.month.clone(),
+        format!("{}{met}", percent(month.availability)),
+        month.outages.to_string(),
+        duration(month.downtime),
+        month
+            .mttr
+            .map_or("-".into(), |m| duration(m.round() as i64)),
+        month.over_budget.map_or("-".into(), |p| format!("{p:.2}%")),
+        month.samples.to_string(),
+    ]
+}
+
+fn headers(budget: Budget) -> [String; 7] {
+    [
+        "Month".into(),
+        "Availability".into(),
+        "Outages".into(),
+        "Downtime".into(),
+        "MTTR".into(),
+        format!("Over {} ms", budget.latency),
+        "Samples".into(),
+    ]
+}
+
+fn markdown(reports: &[Report]) -> String {
+    let mut out = String::from("# Availability report\n");
+    for report in reports {
+        let budget = report.budget;
+        let _ = write!(
+            out,
+            "\n## {}\n\nPromised: {}% availability, {} ms latency\n\n",
+            report.target, budget.availability, budget.latency
+        );
+        let _ = writeln!(out, "| {} |", headers(budget).join(" | "));
+        let _ = writeln!(out, "|{}", "---|".repeat(7));
+        for month in &report.months {
+            let _ = writeln!(out, "| {} |", columns(month).join(" | "));
+        }
+    }
+    out
+}
+
+fn html(reports: &[Report]) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Availability report</title>\n<style>\n\
+         body { font-family: sans-serif; }\n\
+         table { border-collapse: collapse; }\n\
+         th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: right; }\n\
+         .missed { color: #c00; }\n</style>\n</head>\n<body>\n\
+         <h1>Availability report</h1>\n",
+    );
+    for report in reports {
+        let budget = report.budget;
+        let _ = write!(
+            out,
+            "<h2>{}</h2>\n<p>Promised: {}% availability, {} ms latency</p>\n<table>\n<tr>",
+            escape(&report.target),
+            budget.availability,
+            budget.latency
+        );
+        for header in headers(budget) {
+            let _ = write!(out, "<th>{}</th>", escape(&header));
+        }
+        out.push_str("</tr>\n");
+        for month in &report.months {
+            let class = if month.availability_met == Some(false) {
+                " class=\"missed\""
+            } else {
+                ""
+            };
+            let _ = write!(out, "<tr{class}>");
+            for column in columns(month) {
+                let _ = write!(out, "<td>{}</td>", escape(&column));
+            }
+            out.push_str("</tr>\n");
+        }
+        out.push_str("</table>\n");
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Formats seconds like `1h 2m 3s`
+fn duration(secs: i64) -> String {
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{h}h {m}m {s}s")
+    } else if m > 0 {
+        format!("{m}m {s}s")
+    } else {
+        format!("{s}s")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::fs;
+    use std::io::Write as _;
+
+    use crate::ping_stats;
+    use crate::storage::Files;
+
+    #[test]
+    fn months() {
+        let month: Month = "2026-12".parse().unwrap();
+        assert_eq!(month.to_string(), "2026-12");
+        assert_eq!(month.next().to_string(), "2027-01");
+        assert!("2026-13".parse::<Month>().is_err());
+
+        let zone: Zone = "Europe/Berlin".parse().unwrap();
+        let start = month.start(&zone);
+        assert_eq!(start, 1796079600); // 2026-11-30 23:00 UTC
+        assert_eq!(Month::of(start, &zone), Some(month));
+        assert_eq!(Month::of(start - 1, &zone).unwrap().month, 11);
+
+        let january: Month = "2026-01".parse().unwrap();
+        assert!(valid_range(january, january));
+        assert!(valid_range(january, month));
+        assert!(!valid_range(month, january));
+        assert!(valid_range("2024-01".parse().unwrap(), month));
+        assert!(!valid_range("2023-12".parse().unwrap(), month));
+    }
+
+    #[test]
+    fn report() {
+        let dir = std::env::temp_dir().join(format!("ping-log-report-{}", std::process::id()));
+        let storage = Files::new(dir.clone());
+        let target = "1.1.1.1";
+        let month: Month = "2026-01".parse().unwrap();
+        let start = month.start(&Zone::UTC);
+        let end = month.next().start(&Zone::UTC);
+
+        // A ping per minute in the second half of the month
+        let first = start + 16 * 24 * 60 * 60;
+        let mut pings = Vec::new();
+        let mut slow = 0;
+        for time in (first..end).step_by(60) {
+            pings.push(match time - first {
+                // Too short for an outage
+                600 | 660 => Ping::lost(time, Outcome::Timeout),
+                // 10 minutes and the last one continuing into the next month
+                3600..4200 => Ping::lost(time, Outcome::Timeout),
+                _ if time >= end - 300 => Ping::lost(time, Outcome::Unreachable),
+                _ if time % 600 == 0 => {
+                    slow += 1;
+                    Ping::new(time, 40.0)
+                }
+                _ => Ping::new(time, 10.0),
+            });
+        }
+        pings.extend(
+            (end..end + 600)
+                .step_by(60)
+                .map(|t| Ping::lost(t, Outcome::Timeout)),
+        );
+        pings.push(Ping::new(end + 600, 10.0));
+        // Log files of the UTC days
+        let log_dir = ping_stats::target_dir(&dir, target);
+        fs::create_dir_all(&log_dir).unwrap();
+        for ping in &pings {
+            let day = chrono::DateTime::from_timestamp(ping.time, 0).unwrap();
+            let path = log_dir.join(day.format("%y%m%d.txt").to_string());
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .unwrap();
+            writeln!(file, "{ping}").unwrap();
+        }
+
+        let budget = Budget {
+            availability: 99.9,
+            latency: 30.0,
+        };
+        let report = generate(
+            &storage,
+            target,
+            (month, month.next()),
+            &Zone::UTC,
+            budget,
+            3,
+            end + 3600,
+        );
+        assert_eq!(report.months.len(), 2);
+        let january = &report.months[0];
+        assert_eq!(january.samples, ((end - first) / 60) as usize);
+        assert_eq!(january.lost, 2 + 10 + 5);
+        assert_eq!(january.outages, 2);
+        assert_eq!(january.downtime, 600 + 300);
+        assert_eq!(january.mttr, Some((600.0 + 900.0) / 2.0));
+        let monitored = (end - first) as f64;
+        assert_eq!(
+            january.availability,
+            Some(100.0 * (1.0 - 900.0 / monitored))
+        );
+        assert_eq!(january.availability_met, Some(true));
+        let successful = january.samples - january.lost;
+        assert_eq!(
+            january.over_budget,
+            Some(100.0 * slow as f64 / successful as f64)
+        );
+
+        // Only the remainder of the outage that started in January
+        let february = &report.months[1];
+        assert_eq!((february.outages, february.downtime), (0, 600));
+        assert_eq!(february.availability, Some(100.0 * (1.0 - 600.0 / 3600.0)));
+        assert_eq!(february.availability_met, Some(false));
+
+        let markdown = render(std::slice::from_ref(&report), Format::Markdown);
+        assert!(markdown.contains("| 2026-02 | 83.333% (missed) | 0 | 10m 0s | - | 0.00% | 11 |"));
+        let html = render(&[report], Format::Html);
+        assert!(html.contains("<tr class=\"missed\"><td>2026-02</td>"));
+
+        // Ongoing outages last until now, like for the detector
+        let now = end + 300;
+        let report = generate(
+            &storage,
+            target,
+            (month.next(), month.next()),
+            &Zone::UTC,
+            budget,
+            3,
+            now,
+        );
+        assert_eq!(report.months[0].downtime, 300);
+        assert_eq!(report.months[0].mttr, None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,390 @@
+//! Removal of old log files.
+//!
+//! Logs are removed if they are older than the maximum age or, starting with
+//! the oldest, while all logs together are larger than the maximum size.
+//! The logs of the current day are never removed. Before, the logs are
+//! compacted into hourly and daily rollups, which have their own maximum age.
+
+use std::fmt;
+use std::fs::{self, read_dir};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{TimeDelta, Utc};
+use tracing::{error, info, warn};
+
+use super::ping_stats;
+use super::rollup::{self, Tier};
+use super::storage::Storage;
+
+/// Time between two cleanups
+const INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Limits for the logs of all targets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Policy {
+    /// Maximum age in days, if any
+    pub max_age: Option<u32>,
+    /// Maximum total size in bytes, if any
+    pub max_size: Option<u64>,
+    /// Maximum age of the hourly rollups in days, if any
+    pub hourly_age: Option<u32>,
+    /// Maximum age of the daily rollups in days, if any
+    pub daily_age: Option<u32>,
+}
+
+/// Size in bytes with an optional binary suffix (`K`, `M`, `G`, `T`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Size(pub u64);
+
+impl FromStr for Size {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s
+            .trim()
+            .trim_end_matches(['b', 'B'])
+            .trim_end_matches(['i']);
+        let (number, shift) = match s.char_indices().last() {
+            Some((i, 'k' | 'K')) => (&s[..i], 10),
+            Some((i, 'm' | 'M')) => (&s[..i], 20),
+            Some((i, 'g' | 'G')) => (&s[..i], 30),
+            Some((i, 't' | 'T')) => (&s[..i], 40),
+            _ => (s, 0),
+        };
+        let number: u64 = number.trim().parse().map_err(|_| "invalid size")?;
+        number
+            .checked_mul(1 << shift)
+            .map(Size)
+            .ok_or("size too large")
+    }
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+        let mut value = self.0 as f64;
+        if value < 1024.0 {
+            return write!(f, "{} B", self.0);
+        }
+        for unit in UNITS {
+            value /= 1024.0;
+            if value < 1024.0 || unit == "TiB" {
+                return write!(f, "{value:.1} {unit}");
+            }
+        }
+        unreachable!()
+    }
+}
+
+/// Logs of a target and day
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFile {
+    pub path: PathBuf,
+    /// Date of the log (`%y%m%d`)
+    pub date: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    Age,
+    Size,
+}
+
+/// Result of a cleanup
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    pub dry_run: bool,
+    /// Removed (or to be removed) logs
+    pub removed: Vec<(PathBuf, u64, Reason)>,
+    /// Number of remaining logs
+    pub kept: usize,
+    /// Total size of the remaining logs
+    pub kept_size: u64,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verb = if self.dry_run {
+            "would remove"
+        } else {
+            "removed"
+        };
+        for (path, size, reason) in &self.removed {
+            let reason = match reason {
+                Reason::Age => "too old",
+                Reason::Size => "size limit",
+            };
+            writeln!(f, "{verb} {} ({}, {reason})", path.display(), Size(*size))?;
+        }
+        let removed_size = self.removed.iter().map(|(_, size, _)| size).sum();
+        writeln!(
+            f,
+            "{verb} {} logs ({}), kept {} logs ({})",
+            self.removed.len(),
+            Size(removed_size),
+            self.kept,
+            Size(self.kept_size)
+        )
+    }
+}
+
+/// Applies the policy to the logs of all targets in `log_dir`.
+///
+/// With `dry_run`, the logs are only reported and not removed.
+pub fn apply(log_dir: &Path, policy: Policy, dry_run: bool) -> Report {
+    let today = Utc::now();
+    let mut report = plan(
+        log_files(log_dir),
+        oldest(policy.max_age, "%y%m%d").as_deref(),
+        policy.max_size,
+        &today.format("%y%m%d").to_string(),
+    );
+    report.dry_run = dry_run;
+
+    let tiers = [
+        (Tier::Hour, policy.hourly_age),
+        (Tier::Day, policy.daily_age),
+    ];
+    for (tier, max_age) in tiers {
+        let oldest = oldest(max_age, tier.file_format());
+        for dir in target_dirs(log_dir) {
+            let rollups = rollup::rollup_dir(&dir, tier);
+            for name in rollup::files(&dir, tier) {
+                let path = rollups.join(&name);
+                let size = fs::metadata(&path).map_or(0, |m| m.len());
+                let stem = name.trim_end_matches(".txt");
+                if oldest.as_deref().is_some_and(|oldest| stem < oldest) {
+                    report.removed.push((path, size, Reason::Age));
+                } else {
+                    report.kept += 1;
+                    report.kept_size += size;
+                }
+            }
+        }
+    }
+
+    if !dry_run {
+        report
+            .removed
+            .retain(|(path, size, _)| match fs::remove_file(path) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("could not remove old log {}: {e}", path.display());
+                    report.kept += 1;
+                    report.kept_size += size;
+                    false
+                }
+            });
+    }
+    report
+}
+
+/// Returns the formatted date (in UTC) `max_age` days ago
+pub fn oldest(max_age: Option<u32>, format: &str) -> Option<String> {
+    let today = Utc::now();
+    max_age.map(|days| {
+        (today - TimeDelta::days(days.into()))
+            .format(format)
+            .to_string()
+    })
+}
+
+/// Compacts the rollups of the logs in `log_dir`
+pub fn compact(log_dir: &Path) {
+    let now = Utc::now().timestamp();
+    // The legacy logs in the log directory itself are not compacted
+    for dir in target_dirs(log_dir).into_iter().skip(1) {
+        if let Err(e) = rollup::compact(&dir, now) {
+            warn!("could not compact the logs of {}: {e}", dir.display());
+        }
+    }
+}
+
+/// Compacts the logs and applies the policy on startup and then periodically.
+pub async fn run(storage: Arc<dyn Storage>, policy: Policy) {
+    let mut interval = tokio::time::interval(INTERVAL);
+    loop {
+        interval.tick().await;
+        // Compacting and removing the logs blocks on the file system
+        let storage = storage.clone();
+        match tokio::task::spawn_blocking(move || storage.retain(policy, false)).await {
+            Ok(report) if !report.removed.is_empty() => {
+                info!("log retention: {}", report.to_string().trim_end());
+            }
+            Ok(_) => {}
+            Err(e) => error!("log retention failed: {e}"),
+        }
+    }
+}
+
+/// Returns the log directory followed by the directories of the targets
+pub fn target_dirs(log_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![log_dir.to_owned()];
+    if let Ok(entries) = read_dir(log_dir) {
+        dirs.extend(
+            entries
+                .filter_map(Result::ok)
+                .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+                .map(|e| e.path()),
+        );
+    }
+    dirs
+}
+
+/// Returns the logs of all targets and the legacy logs in the log directory
+fn log_files(log_dir: &Path) -> Vec<LogFile> {
+    let mut files = Vec::new();
+    for dir in target_dirs(log_dir) {
+        for name in ping_stats::log_files(&dir) {
+            let path = dir.join(&name);
+            let size = fs::metadata(&path).map_or(0, |m| m.len());
+            files.push(LogFile {
+                date: name[..6].to_owned(),
+                path,
+                size,
+            });
+        }
+    }
+    files
+}
+
+/// Selects the logs that are older than `oldest` or exceed the `max_size`
+pub fn plan(
+    mut files: Vec<LogFile>,
+    oldest: Option<&str>,
+    max_size: Option<u64>,
+    today: &str,
+) -> Report {
+    // Oldest first
+    files.sort_by(|a, b| a.date.cmp(&b.date).then_with(|| a.path.cmp(&b.path)));
+
+    let mut report = Report::default();
+    let mut total: u64 = files.iter().map(|f| f.size).sum();
+    for file in files {
+        let reason = if file.date.as_str() >= today {
+            None
+        } else if oldest.is_some_and(|oldest| file.date.as_str() < oldest) {
+            Some(Reason::Age)
+        } else if max_size.is_some_and(|max| total > max) {
+            Some(Reason::Size)
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            total -= file.size;
+            report.removed.push((file.path, file.size, reason));
+        } else {
+            report.kept += 1;
+            report.kept_size += file.size;
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn size() {
+        assert_eq!("1234".parse(), Ok(Size(1234)));
+        assert_eq!("10k".parse(), Ok(Size(10 << 10)));
+        assert_eq!("500M".parse(), Ok(Size(500 << 20)));
+        assert_eq!("2GiB".parse(), Ok(Size(2 << 30)));
+        assert_eq!("1 TB".parse(), Ok(Size(1 << 40)));
+        assert!("".parse::<Size>().is_err());
+        assert!("M".parse::<Size>().is_err());
+        assert!("-1".parse::<Size>().is_err());
+        assert!("99999999T".parse::<Size>().is_err());
+
+        assert_eq!(Size(100).to_string(), "100 B");
+        assert_eq!(Size(1536).to_string(), "1.5 KiB");
+        assert_eq!(Size(3 << 30).to_string(), "3.0 GiB");
+    }
+
+    #[test]
+    fn policies() {
+        let file = |target: &str, date: &str, size| LogFile {
+            path: Path::new(target).join(format!("{date}.txt")),
+            date: date.into(),
+            size,
+        };
+        let files = vec![
+            file("a", "191129", 10),
+            file("b", "191201", 10),
+            file("a", "191202", 10),
+            file("a", "191203", 10),
+            file("b", "191203", 10),
+        ];
+        let removed = |report: Report| -> Vec<(String, Reason)> {
+            report
+                .removed
+                .into_iter()
+                .map(|(p, _, r)| (p.display().to_string(), r))
+                .collect()
+        };
+
+        let report = plan(files.clone(), None, None, "191203");
+        assert_eq!(report.kept, 5);
+        assert!(report.removed.is_empty());
+
+        let report = plan(files.clone(), Some("191201"), None, "191203");
+        assert_eq!(removed(report), [("a/191129.txt".into(), Reason::Age)]);
+
+        let report = plan(files.clone(), Some("191201"), Some(25), "191203");
+        assert_eq!((report.kept, report.kept_size), (2, 20));
+        assert_eq!(
+            removed(report),
+            [
+                ("a/191129.txt".into(), Reason::Age),
+                ("b/191201.txt".into(), Reason::Size),
+                ("a/191202.txt".into(), Reason::Size),
+            ]
+        );
+
+        // The current logs are kept
+        let report = plan(files, None, Some(0), "191203");
+        assert_eq!((report.kept, report.kept_size), (2, 20));
+    }
+
+    #[test]
+    fn dry_run() {
+        let dir = std::env::temp_dir().join(format!("ping-log-retention-{}", std::process::id()));
+        let target = dir.join("1.1.1.1");
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("000101.txt"), "1 1\n").unwrap();
+        fs::write(dir.join("000102.txt"), "1 1\n").unwrap();
+        fs::write(target.join("notes.txt"), "").unwrap();
+        fs::create_dir_all(rollup::rollup_dir(&target, Tier::Hour)).unwrap();
+        fs::write(rollup::rollup_dir(&target, Tier::Hour).join("0001.txt"), "").unwrap();
+        fs::create_dir_all(rollup::rollup_dir(&target, Tier::Day)).unwrap();
+        fs::write(rollup::rollup_dir(&target, Tier::Day).join("00.txt"), "").unwrap();
+        let policy = Policy {
+            max_age: Some(7),
+            max_size: None,
+            hourly_age: Some(365),
+            daily_age: None,
+        };
+
+        let report = apply(&dir, policy, true);
+        assert_eq!(report.removed.len(), 3);
+        assert!(report
+            .to_string()
+            .ends_with("would remove 3 logs (8 B), kept 1 logs (0 B)\n"));
+        assert!(target.join("000101.txt").exists());
+
+        let report = apply(&dir, policy, false);
+        assert_eq!(report.removed.len(), 3);
+        assert!(!target.join("000101.txt").exists());
+        assert!(!dir.join("000102.txt").exists());
+        assert!(target.join("notes.txt").exists());
+        assert!(rollup::rollup_dir(&target, Tier::Day)
+            .join("00.txt")
+            .exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
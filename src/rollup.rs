@@ -0,0 +1,635 @@
+//! Hourly and daily rollups of the ping logs.
+//!
+//! Complete hours and days are compacted into aggregate records, which are
+//! kept much longer than the raw logs. The records are stored in
+//! `<log dir>/rollups/hourly/<yymm>.txt` and `<log dir>/rollups/daily/<yy>.txt`.
+//! Ranges before the oldest raw log are served from the rollups.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, read_dir, OpenOptions};
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::DateTime;
+
+use super::ping::{Outcome, Ping};
+use super::ping_aggregate::{self, Bins, Bucket, Distribution, Histogram, Zone};
+use super::ping_stats;
+
+/// Relative width of the sketch bins (1% accuracy of the percentiles)
+const GAMMA: f64 = 1.02;
+/// Smallest distinguished response time in ms
+const MIN_RTT: f64 = 0.001;
+/// Pings may be logged this many seconds after their time
+const DELAY: i64 = 300;
+
+/// Mergeable approximation of the response time distribution
+///
+/// Counts the response times in logarithmic bins, bin `i` covers
+/// `(GAMMA^(i-1), GAMMA^i]`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sketch(BTreeMap<i32, u64>);
+
+impl Sketch {
+    pub fn add(&mut self, rtt: f64) {
+        let bin = (rtt.max(MIN_RTT).ln() / GAMMA.ln()).ceil() as i32;
+        *self.0.entry(bin).or_default() += 1;
+    }
+
+    pub fn merge(&mut self, other: &Sketch) {
+        for (bin, count) in &other.0 {
+            *self.0.entry(*bin).or_default() += count;
+        }
+    }
+
+    /// Representative response times with their counts in ascending order
+    fn values(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        self.0
+            .iter()
+            .map(|(bin, count)| (2.0 * GAMMA.powi(*bin) / (GAMMA + 1.0), *count))
+    }
+
+    /// Nearest-rank percentile
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        let total: u64 = self.0.values().sum();
+        let rank = ((p / 100.0 * total as f64).ceil() as u64).clamp(1, total.max(1));
+        let mut seen = 0;
+        for (value, count) in self.values() {
+            seen += count;
+            if seen >= rank {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for Sketch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (bin, count)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{bin}:{count}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Sketch {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        s.split(',')
+            .filter(|b| !b.is_empty())
+            .map(|b| {
+                let (bin, count) = b.split_once(':').ok_or(())?;
+                Ok((bin.parse().map_err(|_| ())?, count.parse().map_err(|_| ())?))
+            })
+            .collect::<std::result::Result<_, _>>()
+            .map(Sketch)
+    }
+}
+
+/// Aggregated pings of an hour or day (durations in ms)
+///
+/// The line format is `<time> count=<n> lost=<sum of loss>` followed by
+/// `replies=<n> sum= min= max= sketch=` if any ping succeeded.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Record {
+    /// Begin of the hour or day
+    pub time: i64,
+    pub count: u64,
+    /// Sum of the lost fractions
+    pub lost: f64,
+    /// Number of successful pings
+    pub replies: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+    pub sketch: Sketch,
+}
+
+impl Record {
+    pub fn new(time: i64) -> Record {
+        Record {
+            time,
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, ping: &Ping) {
+        self.count += 1;
+        self.lost += ping.loss();
+        if ping.outcome == Outcome::Success {
+            let rtt = ping.ping;
+            if self.replies == 0 {
+                (self.min, self.max) = (rtt, rtt);
+            }
+            self.replies += 1;
+            self.sum += rtt;
+            self.min = self.min.min(rtt);
+            self.max = self.max.max(rtt);
+            self.sketch.add(rtt);
+        }
+    }
+
+    pub fn merge(&mut self, other: &Record) {
+        if other.replies > 0 {
+            if self.replies == 0 {
+                (self.min, self.max) = (other.min, other.max);
+            }
+            self.min = self.min.min(other.min);
+            self.max = self.max.max(other.max);
+        }
+        self.count += other.count;
+        self.lost += other.lost;
+        self.replies += other.replies;
+        self.sum += other.sum;
+        self.sketch.merge(&other.sketch);
+    }
+
+    /// Percentile, limited to the measured range
+    fn percentile(&self, p: f64) -> Option<f64> {
+        let value = self.sketch.percentile(p)?;
+        Some(round(value.clamp(self.min, self.max)))
+    }
+
+    pub fn bucket(&self, time: i64) -> Bucket {
+        let loss = if self.count > 0 {
+            round(self.lost / self.count as f64)
+        } else {
+            0.0
+        };
+        let replies = self.replies > 0;
+        Bucket {
+            time,
+            count: self.count as usize,
+            loss,
+            min: replies.then_some(self.min),
+            avg: replies.then(|| round(self.sum / self.replies as f64)),
+            max: replies.then_some(self.max),
+            p50: self.percentile(50.0),
+            p90: self.percentile(90.0),
+            p95: self.percentile(95.0),
+            p99: self.percentile(99.0),
+        }
+    }
+}
+
+fn round(value: f64) -> f64 {
+    (value * 1000.0).round() / 1000.0
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} count={} lost={}", self.time, self.count, self.lost)?;
+        if self.replies > 0 {
+            write!(
+                f,
+                " replies={} sum={} min={} max={} sketch={}",
+                self.replies, self.sum, self.min, self.max, self.sketch
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Record {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut columns = s.split_whitespace();
+        let mut record = Record::new(columns.next().ok_or(())?.parse().map_err(|_| ())?);
+        for column in columns {
+            let (key, value) = column.split_once('=').ok_or(())?;
+            match key {
+                "count" => record.count = value.parse().map_err(|_| ())?,
+                "lost" => record.lost = value.parse().map_err(|_| ())?,
+                "replies" => record.replies = value.parse().map_err(|_| ())?,
+                "sum" => record.sum = value.parse().map_err(|_| ())?,
+                "min" => record.min = value.parse().map_err(|_| ())?,
+                "max" => record.max = value.parse().map_err(|_| ())?,
+                "sketch" => record.sketch = value.parse()?,
+                _ => {}
+            }
+        }
+        Ok(record)
+    }
+}
+
+/// Resolution of the rollups
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tier {
+    /// Hours (in UTC)
+    Hour,
+    /// Days (in UTC)
+    Day,
+}
+
+impl Tier {
+    /// Name of the tier in paths
+    pub fn name(self) -> &'static str {
+        match self {
+            Tier::Hour => "hourly",
+            Tier::Day => "daily",
+        }
+    }
+
+    /// Format of the date (in UTC) in the filenames, one file per month or year
+    pub fn file_format(self) -> &'static str {
+        match self {
+            Tier::Hour => "%y%m",
+            Tier::Day => "%y",
+        }
+    }
+
+    /// Length in seconds
+    pub fn seconds(self) -> i64 {
+        match self {
+            Tier::Hour => 3600,
+            Tier::Day => 86400,
+        }
+    }
+
+    /// Returns the begin of the hour or day
+    pub fn floor(self, time: i64) -> i64 {
+        time - time.rem_euclid(self.seconds())
+    }
+
+    /// Returns the begin of the next hour or day
+    pub fn next(self, time: i64) -> i64 {
+        self.floor(time) + self.seconds()
+    }
+}
+
+/// Returns the directory of the rollups of a target
+pub fn rollup_dir(dir: &Path, tier: Tier) -> PathBuf {
+    dir.join("rollups").join(tier.name())
+}
+
+/// Returns the filenames of the rollups in alphabetical order
+pub fn files(dir: &Path, tier: Tier) -> Vec<String> {
+    let Ok(entries) = read_dir(rollup_dir(dir, tier)) else {
+        return Vec::new();
+    };
+    let mut files: Vec<_> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".txt"))
+        .collect();
+    files.sort_unstable();
+    files
+}
+
+fn read_file(path: &Path) -> Vec<Record> {
+    fs::read_to_string(path)
+        .map(|input| input.lines().filter_map(|l| l.parse().ok()).collect())
+        .unwrap_or_default()
+}
+
+/// Returns all records of the tier in ascending order
+fn read_records(dir: &Path, tier: Tier) -> Vec<Record> {
+    let rollups = rollup_dir(dir, tier);
+    files(dir, tier)
+        .into_iter()
+        .flat_map(|name| read_file(&rollups.join(name)))
+        .collect()
+}
+
+fn last_record(dir: &Path, tier: Tier) -> Option<Record> {
+    let name = files(dir, tier).pop()?;
+    read_file(&rollup_dir(dir, tier).join(name)).pop()
+}
+
+fn write_records(dir: &Path, tier: Tier, records: impl IntoIterator<Item = Record>) -> Result<()> {
+    let rollups = rollup_dir(dir, tier);
+    let mut files = BTreeMap::<String, String>::new();
+    for record in records {
+        let date = DateTime::from_timestamp(record.time, 0).unwrap_or_default();
+        let name = date.format(tier.file_format()).to_string() + ".txt";
+        files
+            .entry(name)
+            .or_default()
+            .push_str(&format!("{record}\n"));
+    }
+    if !files.is_empty() {
+        fs::create_dir_all(&rollups)?;
+    }
+    for (name, lines) in files {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(rollups.join(name))?;
+        file.write_all(lines.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Adds the ping to the record of its hour or day
+pub fn add(records: &mut BTreeMap<i64, Record>, tier: Tier, ping: &Ping) {
+    let time = tier.floor(ping.time);
+    records
+        .entry(time)
+        .or_insert_with(|| Record::new(time))
+        .add(ping);
+}
+
+/// Compacts the hours and days of the logs of a target that are complete at
+/// `now` and have not been rolled up yet.
+///
+/// Returns the number of new records.
+pub fn compact(dir: &Path, now: i64) -> Result<usize> {
+    let mut tiers = [Tier::Hour, Tier::Day].map(|tier| {
+        let (next, end) = pending(tier, last_record(dir, tier).map(|r| r.time), now);
+        (tier, next, end, BTreeMap::<i64, Record>::new())
+    });
+
+    for name in ping_stats::log_files(dir) {
+        let Some((first, last)) = ping_stats::log_range(&name) else {
+            continue;
+        };
+        let needed = |(_, next, end, _): &(Tier, i64, i64, _)| last > *next && first < *end;
+        if !tiers.iter().any(needed) {
+            continue;
+        }
+        for ping in ping_stats::read_log_file(dir, Path::new(&name)) {
+            for (tier, next, end, records) in &mut tiers {
+                if (*next..*end).contains(&ping.time) {
+                    add(records, *tier, &ping);
+                }
+            }
+        }
+    }
+
+    let mut count = 0;
+    for (tier, _, _, records) in tiers {
+        count += records.len();
+        write_records(dir, tier, records.into_values())?;
+    }
+    Ok(count)
+}
+
+/// Raw pings and rollups of a target
+pub trait Source {
+    /// Begin of the oldest raw pings (`i64::MAX` if there are none)
+    fn raw_start(&self) -> i64;
+    /// Raw pings between `start` (inclusive) and `end` (exclusive)
+    fn pings(&self, start: i64, end: i64) -> Vec<Ping>;
+    /// All records of the tier in ascending order
+    fn records(&self, tier: Tier) -> Vec<Record>;
+}
+
+/// Rollups and raw pings in the log directory of a target
+pub struct Dir<'a>(pub &'a Path);
+
+impl Source for Dir<'_> {
+    fn raw_start(&self) -> i64 {
+        ping_stats::log_files(self.0)
+            .first()
+            .and_then(|name| ping_stats::log_day(name))
+            .unwrap_or(i64::MAX)
+    }
+
+    fn pings(&self, start: i64, end: i64) -> Vec<Ping> {
+        // The log is read from the newest to the oldest ping
+        ping_stats::read_log(self.0, 0, usize::MAX, end, start)
+    }
+
+    fn records(&self, tier: Tier) -> Vec<Record> {
+        read_records(self.0, tier)
+    }
+}
+
+/// Returns the begin of the records after `last` and the end of the ones
+/// complete at `now`, which can be compacted.
+pub fn pending(tier: Tier, last: Option<i64>, now: i64) -> (i64, i64) {
+    // Legacy daily records begin at local midnight and end after it
+    let next = last.map_or(i64::MIN, |time| tier.next(time + tier.seconds() - 1));
+    (next, tier.floor(now - DELAY))
+}
+
+/// Returns the rollups before `raw_start`, the daily ones only if there are
+/// no hourly ones.
+fn rollups(source: &impl Source, raw_start: i64) -> Vec<Record> {
+    let hourly = source.records(Tier::Hour);
+    // Hourly records are used from the first complete day on
+    let hourly_start = hourly.first().map_or(raw_start, |r| {
+        let day = Tier::Day.floor(r.time);
+        if day == r.time {
+            day
+        } else {
+            Tier::Day.next(day)
+        }
+    });
+    let daily = source
+        .records(Tier::Day)
+        .into_iter()
+        .filter(|r| r.time < hourly_start.min(raw_start));
+    let hourly = hourly
+        .into_iter()
+        .filter(|r| r.time >= hourly_start && r.time < raw_start);
+    daily.chain(hourly).collect()
+}
+
+/// Returns the rollups and raw pings of the range as records, or `None` if
+/// the range is covered by the raw logs.
+fn records(source: &impl Source, start: i64, end: i64) -> Option<Vec<Record>> {
+    let raw_start = source.raw_start();
+    if start >= raw_start {
+        return None;
+    }
+    let mut records: Vec<_> = rollups(source, raw_start)
+        .into_iter()
+        .filter(|r| r.time < end && Tier::Day.next(r.time) > start)
+        .collect();
+    if records.is_empty() {
+        return None;
+    }
+    if end > raw_start {
+        records.extend(source.pings(raw_start, end).iter().map(|ping| {
+            let mut record = Record::new(ping.time);
+            record.add(ping);
+            record
+        }));
+    }
+    Some(records)
+}
+
+/// Aggregates the pings of a target like [ping_aggregate::aggregate],
+/// using the rollups for the range before the raw logs.
+///
+/// The rollups are assigned to the bucket of their begin, so their
+/// resolution is at most an hour or day.
+pub fn aggregate(
+    source: &impl Source,
+    start: i64,
+    end: i64,
+    width: i64,
+    zone: Zone,
+) -> Vec<Bucket> {
+    let Some(records) = records(source, start, end) else {
+        let pings = source.pings(start, end);
+        return ping_aggregate::aggregate(pings, start, end, width, zone);
+    };
+    let records = records.into_iter().map(|r| (r.time, r));
+    ping_aggregate::group(records, start, end, width, zone, |s: &mut Record, r| {
+        s.merge(&r)
+    })
+    .into_iter()
+    .map(|(time, record)| record.bucket(time))
+    .collect()
+}
+
+/// Computes the distribution of a target like [ping_aggregate::distribution],
+/// using the rollups for the range before the raw logs.
+pub fn distribution(source: &impl Source, start: i64, end: i64, bins: Bins) -> Distribution {
+    let Some(records) = records(source, start, end) else {
+        let pings = source.pings(start, end);
+        return ping_aggregate::distribution(pings, start, end, bins);
+    };
+    let mut total = Record::new(start);
+    for record in records.iter().filter(|r| (start..end).contains(&r.time)) {
+        total.merge(record);
+    }
+    let replies = total.replies > 0;
+    let mut histogram = Histogram::empty(
+        bins,
+        replies.then_some(total.min),
+        replies.then_some(total.max),
+    );
+    for (rtt, count) in total.sketch.values() {
+        histogram.add(rtt.clamp(total.min, total.max), count as usize);
+    }
+    Distribution {
+        stats: total.bucket(start),
+        histogram,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::ping::Burst;
+
+    #[test]
+    fn sketch() {
+        let mut sketch = Sketch::default();
+        for rtt in 1..=100 {
+            sketch.add(rtt as f64);
+        }
+        for (p, exact) in [(50.0, 50.0), (90.0, 90.0), (99.0, 99.0), (100.0, 100.0)] {
+            let value = sketch.percentile(p).unwrap();
+            assert!((value - exact).abs() / exact < 0.01, "p{p}: {value}");
+        }
+        assert_eq!(Sketch::default().percentile(50.0), None);
+
+        let text = sketch.to_string();
+        assert_eq!(text.parse(), Ok(sketch.clone()));
+
+        let mut merged = Sketch::default();
+        merged.merge(&sketch);
+        merged.merge(&sketch);
+        assert_eq!(merged.0.values().sum::<u64>(), 200);
+    }
+
+    #[test]
+    fn record() {
+        let mut record = Record::new(3600);
+        record.add(&Ping::new(3600, 10.0));
+        record.add(&Ping::lost(3660, Outcome::Timeout));
+        record.add(&Ping {
+            burst: Some(Burst::new(4, &[19.0, 21.0, 20.0], 0.0)),
+            ..Ping::new(3720, 20.0)
+        });
+        let line = record.to_string();
+        assert!(line.starts_with("3600 count=3 lost=1.25 replies=2 sum=30 min=10 max=20 sketch="));
+        assert_eq!(line.parse(), Ok(record.clone()));
+
+        let mut other = Record::new(7200);
+        other.add(&Ping::lost(7200, Outcome::Unreachable));
+        assert_eq!(other.to_string(), "7200 count=1 lost=1");
+        other.merge(&record);
+        let bucket = other.bucket(0);
+        assert_eq!((bucket.count, bucket.loss), (4, 0.563));
+        assert_eq!(
+            (bucket.min, bucket.avg, bucket.max),
+            (Some(10.0), Some(15.0), Some(20.0))
+        );
+        assert_eq!(bucket.p99, Some(20.0));
+        assert!((bucket.p50.unwrap() - 10.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn tiers() {
+        let time = 1626457680;
+        assert_eq!(Tier::Hour.floor(time), 1626454800);
+        assert_eq!(Tier::Hour.next(time), 1626458400);
+        assert_eq!(Tier::Day.floor(time), 1626393600);
+        assert_eq!(Tier::Day.next(time), 1626480000);
+        assert_eq!(pending(Tier::Day, Some(1626393600), time).0, 1626480000);
+        // Legacy record of a local day in UTC+2
+        assert_eq!(pending(Tier::Day, Some(1626386400), time).0, 1626480000);
+        assert_eq!(pending(Tier::Hour, None, time).1, 1626454800);
+    }
+
+    #[test]
+    fn rollups() {
+        let dir = std::env::temp_dir().join(format!("ping-log-rollup-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let today = 1626393600;
+        let day = |days: i64| today - days * 86400;
+        let date = |days: i64| DateTime::from_timestamp(day(days), 0).unwrap();
+        // Two hours and two minutes into today
+        let now = today + 2 * 3600 + 120 + DELAY;
+        // Two days ago, yesterday and today
+        for days in 0..3 {
+            let start = day(days);
+            let lines: String = (0..4)
+                .map(|h| format!("{}\n", Ping::new(start + h * 3600 + 60, (10 + days) as f64)))
+                .collect();
+            let name = date(days).format("%y%m%d.txt").to_string();
+            fs::write(dir.join(name), lines).unwrap();
+        }
+
+        // Today is not complete
+        assert_eq!(compact(&dir, now).unwrap(), 8 + 2 + 2);
+        assert_eq!(compact(&dir, now).unwrap(), 0);
+        let daily = read_records(&dir, Tier::Day);
+        assert_eq!(daily.len(), 2);
+        assert_eq!(
+            (daily[0].time, daily[0].count, daily[0].min),
+            (day(2), 4, 12.0)
+        );
+
+        // Remove the raw logs of two days ago
+        fs::remove_file(dir.join(date(2).format("%y%m%d.txt").to_string())).unwrap();
+        let buckets = aggregate(&Dir(&dir), day(2), day(0), 86400, Zone::UTC);
+        assert_eq!(buckets.len(), 2);
+        assert_eq!((buckets[0].count, buckets[0].avg), (4, Some(12.0)));
+        assert_eq!((buckets[1].count, buckets[1].avg), (4, Some(11.0)));
+        let buckets = aggregate(&Dir(&dir), day(2), day(2) + 4 * 3600, 3600, Zone::UTC);
+        assert_eq!(
+            buckets.iter().map(|b| b.count).collect::<Vec<_>>(),
+            [1, 1, 1, 1]
+        );
+
+        let bins = Bins {
+            count: 2,
+            min: Some(10.0),
+            max: Some(12.0),
+        };
+        let distribution = distribution(&Dir(&dir), day(2), day(0), bins);
+        assert_eq!(distribution.stats.count, 8);
+        assert_eq!(distribution.histogram.counts, [0, 8]);
+
+        // Covered by the raw logs
+        let buckets = aggregate(&Dir(&dir), day(1), day(0), 86400, Zone::UTC);
+        assert_eq!(buckets[0].p50, Some(11.0));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+//! Shared schedule of all probes.
+//!
+//! The monitors wait for their ticks and run their probes through the
+//! scheduler, which limits the number of concurrent probes, enforces their
+//! timeouts and measures how late the ticks are (scheduler lag).
+
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::sync::Semaphore;
+use tracing::warn;
+
+/// Ticks that are later than this are logged
+const LAG_WARNING: Duration = Duration::from_secs(1);
+
+/// Statistics about the difference between the actual and intended tick times
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct Lag {
+    /// Number of ticks
+    pub ticks: u64,
+    /// Lag of the last tick in ms
+    pub last: f64,
+    /// Exponentially weighted average of the lag in ms
+    pub avg: f64,
+    /// Maximum lag in ms
+    pub max: f64,
+}
+
+impl Lag {
+    fn update(&mut self, lag: Duration) {
+        let lag = (lag.as_secs_f64() * 10000.0).round() / 10.0;
+        self.avg = if self.ticks == 0 {
+            lag
+        } else {
+            self.avg + (lag - self.avg) / 16.0
+        };
+        self.ticks += 1;
+        self.last = lag;
+        self.max = self.max.max(lag);
+    }
+}
+
+/// Current state of the scheduler
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Status {
+    /// Number of currently running probes
+    pub running: usize,
+    /// Maximum number of concurrent probes
+    pub limit: usize,
+    pub lag: Lag,
+}
+
+pub struct Scheduler {
+    permits: Semaphore,
+    limit: usize,
+    lag: Mutex<Lag>,
+}
+
+impl Scheduler {
+    /// Creates a scheduler that runs at most `limit` probes concurrently
+    pub fn new(limit: usize) -> Scheduler {
+        Scheduler {
+            permits: Semaphore::new(limit),
+            limit,
+            lag: Mutex::new(Lag::default()),
+        }
+    }
+
+    /// Waits for the next multiple of `interval` seconds since the epoch.
+    pub async fn tick(&self, interval: u64) {
+        let epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let next = Duration::from_secs(((epoch.as_secs() + interval) / interval) * interval);
+
+        tokio::time::sleep(next - epoch).await;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        self.record(now.saturating_sub(next));
+    }
+
+    fn record(&self, lag: Duration) {
+        if lag > LAG_WARNING {
+            warn!("scheduler lags behind by {lag:?}");
+        }
+        self.lag.lock().unwrap().update(lag);
+    }
+
+    /// Runs the probe as soon as the concurrency limit permits it.
+    ///
+    /// Returns `None` if the probe did not finish within `timeout`, which
+    /// does not include the time waiting for other probes.
+    pub async fn run<T>(&self, timeout: Duration, probe: impl Future<Output = T>) -> Option<T> {
+        let _permit = self.permits.acquire().await.expect("scheduler closed");
+        tokio::time::timeout(timeout, probe).await.ok()
+    }
+
+    pub fn status(&self) -> Status {
+        Status {
+            running: self.limit - self.permits.available_permits(),
+            limit: self.limit,
+            lag: *self.lag.lock().unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn lag() {
+        let mut lag = Lag::default();
+        lag.update(Duration::from_millis(16));
+        assert_eq!(
+            lag,
+            Lag {
+                ticks: 1,
+                last: 16.0,
+                avg: 16.0,
+                max: 16.0
+            }
+        );
+        lag.update(Duration::from_millis(0));
+        assert_eq!(
+            lag,
+            Lag {
+                ticks: 2,
+                last: 0.0,
+                avg: 15.0,
+                max: 16.0
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let scheduler = Scheduler::new(1);
+        let short = Duration::from_millis(10);
+        assert_eq!(scheduler.run(short, async { 1 }).await, Some(1));
+        let slow = tokio::time::sleep(Duration::from_secs(5));
+        assert_eq!(scheduler.run(short, slow).await, None);
+        assert_eq!(scheduler.status().running, 0);
+    }
+
+    #[tokio::test]
+    async fn limit() {
+        let scheduler = Arc::new(Scheduler::new(2));
+        let running = Arc::new(AtomicUsize::new(0));
+        let max = Arc::new(AtomicUsize::new(0));
+
+        let tasks: Vec<_> = (0..6)
+            .map(|_| {
+                let (scheduler, running, max) = (scheduler.clone(), running.clone(), max.clone());
+                tokio::spawn(async move {
+                    scheduler
+                        .run(Duration::from_secs(1), async {
+                            let current = running.fetch_add(1, Ordering::SeqCst) + 1;
+                            max.fetch_max(current, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(20)).await;
+                            running.fetch_sub(1, Ordering::SeqCst);
+                        })
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(task.await.unwrap(), Some(()));
+        }
+        assert_eq!(max.load(Ordering::SeqCst), 2);
+    }
+}
@@ -6,30 +6,60 @@ use std::time::Duration;
 use axum::body::Body;
 use axum::error_handling::HandleErrorLayer;
 use axum::extract::{Json, Query, State};
-use axum::http::{Request, StatusCode};
+use axum::http::{header, HeaderMap, Request, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::response::IntoResponse;
 use axum::routing::get;
 use axum::BoxError;
-use serde::Deserialize;
+use futures_util::stream::{self, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
 use tower::{ServiceBuilder, ServiceExt};
 use tower_http::compression::CompressionLayer;
 use tower_http::services::{ServeDir, ServeFile};
 use tower_http::trace::TraceLayer;
 use tracing::error;
 
+use super::alert::{self, Engine};
+use super::health::{self, Health};
 use super::hw;
 use super::mc;
+use super::metrics::{self, ProbeMetrics};
+use super::outage::{self, Detector};
+use super::ping::Ping;
+use super::ping_aggregate::{self, Zone};
 use super::ping_stats;
+use super::report::{self, Budget, Month};
+use super::scheduler::{self, Scheduler};
+use super::storage::Storage;
+use super::stream::{Hub, Update};
+use super::trace;
 
-struct AppState {
-    log_dir: PathBuf,
-    mc_hosts: Arc<RwLock<Vec<mc::Status>>>,
-    web_dir: PathBuf,
+/// State shared between the monitors and the webserver
+pub struct AppState {
+    pub log_dir: PathBuf,
+    pub targets: Vec<String>,
+    pub mc_hosts: Arc<RwLock<Vec<mc::Status>>>,
+    pub web_dir: PathBuf,
+    pub scheduler: Arc<Scheduler>,
+    /// Health of the monitors (in the order of the targets)
+    pub health: Vec<Arc<Health>>,
+    /// Probe results of the monitors (in the order of the targets)
+    pub probes: Vec<Arc<ProbeMetrics>>,
+    /// Storage of the pings
+    pub storage: Arc<dyn Storage>,
+    /// Live updates for `/api/stream`
+    pub hub: Arc<Hub>,
+    pub alerts: Arc<Engine>,
+    pub outages: Arc<Detector>,
+    /// Default service level of the reports
+    pub budget: Budget,
 }
 
-#[derive(Deserialize, Copy, Clone)]
+#[derive(Deserialize, Clone)]
 #[serde(default)]
 struct TimeQuery {
+    /// Ping target, defaults to the first configured target
+    target: Option<String>,
     offset: usize,
     count: usize,
     start: i64,
@@ -38,6 +68,7 @@ struct TimeQuery {
 impl Default for TimeQuery {
     fn default() -> Self {
         Self {
+            target: None,
             offset: 0,
             count: 60,
             start: 0,
@@ -45,21 +76,138 @@ impl Default for TimeQuery {
         }
     }
 }
+/// Maximum number of histogram bins
+const MAX_BINS: usize = 1000;
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct StatsQuery {
+    /// Ping target, defaults to the first configured target
+    target: Option<String>,
+    /// Begin of the range, defaults to one day before `end`
+    start: i64,
+    /// End of the range (exclusive), defaults to now
+    end: i64,
+    /// Width of the buckets in seconds
+    bucket: i64,
+    /// Time zone the buckets are aligned to (`local`, `UTC`, `+02:00` or
+    /// `Europe/Berlin`)
+    tz: String,
+}
+impl Default for StatsQuery {
+    fn default() -> Self {
+        Self {
+            target: None,
+            start: 0,
+            end: 0,
+            bucket: 3600,
+            tz: "local".into(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct DistributionQuery {
+    /// Ping target, defaults to the first configured target
+    target: Option<String>,
+    /// Begin of the range, defaults to one day before `end`
+    start: i64,
+    /// End of the range (exclusive), defaults to now
+    end: i64,
+    /// Number of histogram bins
+    bins: usize,
+    /// Lower edge of the histogram in ms, defaults to the minimum
+    min: Option<f64>,
+    /// Upper edge of the histogram in ms, defaults to the maximum
+    max: Option<f64>,
+}
+impl Default for DistributionQuery {
+    fn default() -> Self {
+        Self {
+            target: None,
+            start: 0,
+            end: 0,
+            bins: 20,
+            min: None,
+            max: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct OutageQuery {
+    /// Ping target, defaults to all configured targets
+    target: Option<String>,
+    /// Only outages that end after this time
+    start: i64,
+    /// Only outages that start before this time
+    end: i64,
+    /// Minimum duration in seconds
+    min_duration: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct ReportQuery {
+    /// Ping target, defaults to all configured targets
+    target: Option<String>,
+    /// First month (`2026-01`), defaults to `to`
+    from: Option<String>,
+    /// Last month, defaults to the current one
+    to: Option<String>,
+    /// Time zone of the months
+    tz: String,
+    /// Promised availability in percent, defaults to `--sla-availability`
+    availability: Option<f64>,
+    /// Latency budget in ms, defaults to `--sla-latency`
+    latency: Option<f64>,
+}
+impl Default for ReportQuery {
+    fn default() -> Self {
+        Self {
+            target: None,
+            from: None,
+            to: None,
+            tz: "local".into(),
+            availability: None,
+            latency: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct StreamQuery {
+    /// Ping target, defaults to all configured targets
+    target: Option<String>,
+    /// Resume with the pings logged after this time
+    since: i64,
+}
+
 /// Starts the ping log webserver on the given `ip`
-pub async fn run(
-    ip: SocketAddr,
-    log_dir: PathBuf,
-    web_dir: PathBuf,
-    mc_hosts: Arc<RwLock<Vec<mc::Status>>>,
-) {
+pub async fn run(ip: SocketAddr, state: AppState) {
     println!("Ping server is running on {ip}");
 
     let app = axum::Router::new()
         .route("/api/pings", get(handle_pings))
+        .route("/api/stats", get(handle_stats))
+        .route("/api/distribution", get(handle_distribution))
+        .route("/api/traces", get(handle_traces))
+        .route("/api/targets", get(handle_targets))
         .route("/api/hw", get(handle_hw))
         .route("/api/mc", get(handle_mc))
+        .route("/api/scheduler", get(handle_scheduler))
+        .route("/api/health", get(handle_health))
+        .route("/api/stream", get(handle_stream))
+        .route("/api/alerts", get(handle_alerts))
+        .route("/api/outages", get(handle_outages))
+        .route("/api/uptime", get(handle_uptime))
+        .route("/api/report", get(handle_report))
+        .route("/metrics", get(handle_metrics))
         .route("/", get(serve_index))
-        .fallback_service(ServeDir::new(&web_dir))
+        .fallback_service(ServeDir::new(&state.web_dir))
         .layer(
             ServiceBuilder::new()
                 .layer(CompressionLayer::new())
@@ -68,11 +216,7 @@ pub async fn run(
                 .layer(TraceLayer::new_for_http())
                 .into_inner(),
         )
-        .with_state(Arc::new(AppState {
-            log_dir,
-            mc_hosts,
-            web_dir,
-        }));
+        .with_state(Arc::new(state));
 
     axum::serve(tokio::net::TcpListener::bind(ip).await.unwrap(), app)
         .await
@@ -88,17 +232,116 @@ async fn error_handler(error: BoxError) -> StatusCode {
     }
 }
 
+impl AppState {
+    /// Returns the requested or the default target if it is configured
+    fn target<'a>(&'a self, target: &'a Option<String>) -> Result<&'a str, StatusCode> {
+        match target {
+            Some(target) if self.targets.contains(target) => Ok(target),
+            Some(_) => Err(StatusCode::NOT_FOUND),
+            None => self
+                .targets
+                .first()
+                .map(String::as_str)
+                .ok_or(StatusCode::NOT_FOUND),
+        }
+    }
+}
+
 async fn handle_pings(
     State(state): State<Arc<AppState>>,
     Query(query): Query<TimeQuery>,
-) -> Json<Vec<super::ping::Ping>> {
-    Json(ping_stats::read_log(
-        &state.log_dir,
+) -> Result<Json<Vec<super::ping::Ping>>, StatusCode> {
+    let target = state.target(&query.target)?;
+    // The pings are returned from `start` back to `end`
+    if query.start != 0 && query.start < query.end {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(Json(state.storage.query(
+        target,
+        query.offset,
+        query.count,
+        query.start,
+        query.end,
+    )))
+}
+
+async fn handle_stats(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<StatsQuery>,
+) -> Result<Json<Vec<ping_aggregate::Bucket>>, StatusCode> {
+    let target = state.target(&query.target)?;
+    let zone: Zone = query.tz.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+    let (start, end) = time_range(query.start, query.end)?;
+    match ping_aggregate::bucket_count(start, end, query.bucket) {
+        Some(count) if count <= ping_aggregate::MAX_BUCKETS => {}
+        _ => return Err(StatusCode::BAD_REQUEST),
+    }
+
+    Ok(Json(state.storage.aggregate(
+        target,
+        start,
+        end,
+        query.bucket,
+        zone,
+    )))
+}
+
+async fn handle_distribution(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<DistributionQuery>,
+) -> Result<Json<ping_aggregate::Distribution>, StatusCode> {
+    let target = state.target(&query.target)?;
+    let (start, end) = time_range(query.start, query.end)?;
+    if !(1..=MAX_BINS).contains(&query.bins) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let bins = ping_aggregate::Bins {
+        count: query.bins,
+        min: query.min,
+        max: query.max,
+    };
+    Ok(Json(state.storage.distribution(target, start, end, bins)))
+}
+
+/// Applies the defaults (the last day) to the range of a statistics request
+/// and rejects empty ranges and timestamps out of the supported time range
+fn time_range(start: i64, end: i64) -> Result<(i64, i64), StatusCode> {
+    let end = if end == 0 {
+        chrono::Local::now().timestamp()
+    } else {
+        end
+    };
+    let start = if start == 0 {
+        end.checked_sub(24 * 60 * 60)
+            .ok_or(StatusCode::BAD_REQUEST)?
+    } else {
+        start
+    };
+    let valid = |time| chrono::DateTime::from_timestamp(time, 0).is_some();
+    if start < end && valid(start) && valid(end) {
+        Ok((start, end))
+    } else {
+        Err(StatusCode::BAD_REQUEST)
+    }
+}
+
+async fn handle_traces(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TimeQuery>,
+) -> Result<Json<Vec<trace::Trace>>, StatusCode> {
+    let target = state.target(&query.target)?;
+    Ok(Json(trace::read_traces(
+        &ping_stats::target_dir(&state.log_dir, target),
         query.offset,
         query.count,
         query.start,
         query.end,
-    ))
+    )))
+}
+
+async fn handle_targets(State(state): State<Arc<AppState>>) -> Json<Vec<String>> {
+    Json(state.targets.clone())
 }
 
 async fn handle_hw() -> Json<hw::Status> {
@@ -110,6 +353,183 @@ async fn handle_mc(State(state): State<Arc<AppState>>) -> Json<Vec<mc::Status>>
     Json(mc_state.clone())
 }
 
+async fn handle_scheduler(State(state): State<Arc<AppState>>) -> Json<scheduler::Status> {
+    Json(state.scheduler.status())
+}
+
+/// Returns the health of the monitors, with status 503 if any is unhealthy
+async fn handle_health(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<Vec<health::Status>>) {
+    let status: Vec<_> = state.health.iter().map(|h| h.status()).collect();
+    let code = if status.iter().all(|s| s.healthy) {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(status))
+}
+
+/// Returns the active and recently resolved alerts
+async fn handle_alerts(State(state): State<Arc<AppState>>) -> Json<alert::Alerts> {
+    Json(state.alerts.alerts())
+}
+
+/// Returns the outages, beginning with the newest
+async fn handle_outages(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<OutageQuery>,
+) -> Result<Json<Vec<outage::Outage>>, StatusCode> {
+    let targets = match query.target {
+        Some(_) => vec![state.target(&query.target)?.to_owned()],
+        None => state.targets.clone(),
+    };
+    let now = chrono::Local::now().timestamp();
+    let mut outages: Vec<_> = targets
+        .iter()
+        .flat_map(|target| {
+            state
+                .outages
+                .outages(target, query.start, query.end, query.min_duration, now)
+        })
+        .collect();
+    outages.sort_by_key(|o| std::cmp::Reverse(o.start));
+    Ok(Json(outages))
+}
+
+/// Returns the uptime of the targets over the last day, week and month
+async fn handle_uptime(State(state): State<Arc<AppState>>) -> Json<Vec<outage::Uptime>> {
+    let now = chrono::Local::now().timestamp();
+    Json(
+        state
+            .targets
+            .iter()
+            .map(|target| state.outages.uptime(target, now))
+            .collect(),
+    )
+}
+
+/// Returns the monthly service levels of the targets
+async fn handle_report(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ReportQuery>,
+) -> Result<Json<Vec<report::Report>>, StatusCode> {
+    let targets = match query.target {
+        Some(_) => vec![state.target(&query.target)?.to_owned()],
+        None => state.targets.clone(),
+    };
+    let zone: Zone = query.tz.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+    let now = chrono::Local::now().timestamp();
+    let month = |m: Option<String>| -> Result<Option<Month>, StatusCode> {
+        m.map(|m| m.parse().map_err(|_| StatusCode::BAD_REQUEST))
+            .transpose()
+    };
+    let to = month(query.to)?
+        .or_else(|| Month::of(now, &zone))
+        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+    let from = month(query.from)?.unwrap_or(to);
+    if !report::valid_range(from, to) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let budget = Budget {
+        availability: query.availability.unwrap_or(state.budget.availability),
+        latency: query.latency.unwrap_or(state.budget.latency),
+    };
+
+    // Reads the pings of all the months
+    let reports = tokio::task::spawn_blocking(move || {
+        targets
+            .iter()
+            .map(|target| {
+                report::generate(
+                    state.storage.as_ref(),
+                    target,
+                    (from, to),
+                    &zone,
+                    budget,
+                    state.outages.threshold(),
+                    now,
+                )
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| {
+        error!("could not generate the report: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(Json(reports))
+}
+
+async fn handle_metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let mc = state.mc_hosts.read().unwrap().clone();
+    let body = metrics::render(
+        &state.probes,
+        &state.health,
+        &hw::Status::request(),
+        &mc,
+        &state.scheduler.status(),
+    );
+    (
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        body,
+    )
+}
+
+#[derive(Serialize)]
+struct PingEvent<'a> {
+    target: &'a str,
+    #[serde(flatten)]
+    ping: &'a Ping,
+}
+
+/// Streams the logged pings and status updates as server-sent events.
+///
+/// Reconnecting clients are resumed from the `Last-Event-ID`, which is the
+/// time of the last received ping.
+async fn handle_stream(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<StreamQuery>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, StatusCode> {
+    let targets: Vec<_> = match query.target {
+        Some(_) => vec![state.target(&query.target)?.to_owned()],
+        None => state.targets.clone(),
+    };
+    let last_id = headers
+        .get("last-event-id")
+        .and_then(|id| id.to_str().ok()?.parse().ok())
+        .unwrap_or_default();
+    let since = query.since.max(last_id);
+
+    let client = state.hub.subscribe(state.storage.clone(), targets, since);
+
+    // The current status is sent first, the pings only if resumed
+    let mc = state.mc_hosts.read().unwrap().clone();
+    let current = [Update::Hw(hw::Status::request()), Update::Mc(mc)];
+    let updates = stream::iter(current).chain(stream::unfold(client, |mut client| async {
+        let update = client.next().await?;
+        Some((update, client))
+    }));
+    let events = updates.map(|update| match update {
+        Update::Ping(target, ping) => Event::default()
+            .event("ping")
+            .id(ping.time.to_string())
+            .json_data(PingEvent {
+                target: &target,
+                ping: &ping,
+            }),
+        Update::Hw(status) => Event::default().event("hw").json_data(status),
+        Update::Mc(status) => Event::default().event("mc").json_data(status),
+        Update::Alert(alert) => Event::default().event("alert").json_data(alert),
+        Update::Outage(outage) => Event::default().event("outage").json_data(outage),
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
 async fn serve_index(
     State(state): State<Arc<AppState>>,
     req: Request<Body>,
@@ -120,3 +540,29 @@ async fn serve_index(
         .unwrap()
         .into_response()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn time_ranges() {
+        assert_eq!(
+            time_range(1626457680, 1626461280),
+            Ok((1626457680, 1626461280))
+        );
+        let (start, end) = time_range(0, 1626461280).unwrap();
+        assert_eq!(end - start, 24 * 60 * 60);
+        assert_eq!(
+            time_range(1626461280, 1626457680),
+            Err(StatusCode::BAD_REQUEST)
+        );
+        // Out of the range of the timestamps
+        assert_eq!(
+            time_range(9_000_000_000_000, 9_000_000_003_600),
+            Err(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(time_range(i64::MIN, 0), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(time_range(0, i64::MIN), Err(StatusCode::BAD_REQUEST));
+    }
+}
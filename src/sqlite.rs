@@ -0,0 +1,531 @@
+//! SQLite storage of the pings.
+//!
+//! The pings are stored as log lines with their target and an indexed time,
+//! so range queries only read the requested rows. The rollups are stored in
+//! a separate table with the same line format as the rollup files.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use chrono::Utc;
+use rusqlite::{params, Connection};
+use tracing::{error, warn};
+
+use super::ping::Ping;
+use super::ping_aggregate::{Bins, Bucket, Distribution, Zone};
+use super::ping_stats;
+use super::retention::{self, LogFile, Policy, Reason, Report};
+use super::rollup::{self, Dir, Record, Source, Tier};
+use super::storage::Storage;
+
+const SCHEMA: &str = "
+PRAGMA auto_vacuum = INCREMENTAL;
+PRAGMA journal_mode = WAL;
+CREATE TABLE IF NOT EXISTS pings (
+    target TEXT NOT NULL,
+    time INTEGER NOT NULL,
+    line TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS pings_time ON pings (target, time);
+CREATE TABLE IF NOT EXISTS rollups (
+    target TEXT NOT NULL,
+    tier TEXT NOT NULL,
+    time INTEGER NOT NULL,
+    record TEXT NOT NULL,
+    PRIMARY KEY (target, tier, time)
+);
+";
+
+/// Estimated storage overhead of a ping (row and index) in bytes
+const ROW_OVERHEAD: i64 = 32;
+/// Days of pings that are compacted at once while holding the connection
+const COMPACT_DAYS: i64 = 7;
+
+pub struct Sqlite(Mutex<Connection>);
+
+impl Sqlite {
+    /// Opens or creates the database
+    pub fn open(path: &Path) -> rusqlite::Result<Sqlite> {
+        Sqlite::new(Connection::open(path)?)
+    }
+
+    fn new(conn: Connection) -> rusqlite::Result<Sqlite> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Sqlite(Mutex::new(conn)))
+    }
+
+    /// Imports the text logs and rollups of a target from its log directory.
+    ///
+    /// Only pings newer than the stored ones are imported, so an interrupted
+    /// import can be repeated. Returns the number of imported pings.
+    pub fn import(&self, target: &str, dir: &Path) -> rusqlite::Result<usize> {
+        let mut conn = self.0.lock().unwrap();
+        let tx = conn.transaction()?;
+        let last: Option<i64> = tx.query_row(
+            "SELECT MAX(time) FROM pings WHERE target = ?1",
+            [target],
+            |row| row.get(0),
+        )?;
+
+        let mut count = 0;
+        {
+            let mut insert =
+                tx.prepare("INSERT INTO pings (target, time, line) VALUES (?1, ?2, ?3)")?;
+            for name in ping_stats::log_files(dir) {
+                let mut pings = ping_stats::read_log_file(dir, Path::new(&name));
+                // Oldest first
+                pings.reverse();
+                for ping in pings.iter().filter(|p| last.is_none_or(|l| p.time > l)) {
+                    insert.execute(params![target, ping.time, ping.to_string()])?;
+                    count += 1;
+                }
+            }
+            for tier in [Tier::Hour, Tier::Day] {
+                for record in Dir(dir).records(tier) {
+                    insert_record(&tx, target, tier, &record)?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(count)
+    }
+
+    /// Compacts the complete hours and days that have not been rolled up yet.
+    ///
+    /// The pings are compacted in ranges of [`COMPACT_DAYS`] and the
+    /// connection is released in between, so the pings can still be appended
+    /// and queried meanwhile.
+    fn compact(&self) -> rusqlite::Result<usize> {
+        let now = Utc::now().timestamp();
+        let targets = targets(&self.0.lock().unwrap())?;
+        let mut count = 0;
+        for target in targets {
+            for tier in [Tier::Hour, Tier::Day] {
+                let last: Option<i64> = self.0.lock().unwrap().query_row(
+                    "SELECT MAX(time) FROM rollups WHERE target = ?1 AND tier = ?2",
+                    params![target, tier.name()],
+                    |row| row.get(0),
+                )?;
+                let (mut next, end) = rollup::pending(tier, last, now);
+                while next < end {
+                    let mut conn = self.0.lock().unwrap();
+                    let first: Option<i64> = conn.query_row(
+                        "SELECT MIN(time) FROM pings WHERE target = ?1 AND time >= ?2 AND time < ?3",
+                        params![target, next, end],
+                        |row| row.get(0),
+                    )?;
+                    let Some(first) = first else {
+                        break;
+                    };
+                    // Ranges end at midnight, so no record is split
+                    let until =
+                        end.min(Tier::Day.next(first) + (COMPACT_DAYS - 1) * Tier::Day.seconds());
+
+                    let tx = conn.transaction()?;
+                    let mut records = BTreeMap::new();
+                    for ping in query(&tx, &target, 0, usize::MAX, until, first)? {
+                        rollup::add(&mut records, tier, &ping);
+                    }
+                    for record in records.values() {
+                        insert_record(&tx, &target, tier, record)?;
+                    }
+                    tx.commit()?;
+                    count += records.len();
+                    next = until;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    fn apply(conn: &mut Connection, policy: Policy, dry_run: bool) -> rusqlite::Result<Report> {
+        let today = Utc::now().format("%y%m%d").to_string();
+        // Target and begin of the days by their name in the report
+        let mut names = HashMap::new();
+        let mut days = Vec::new();
+        {
+            let mut stmt = conn.prepare(
+                "SELECT target,
+                    substr(strftime('%Y%m%d', time, 'unixepoch'), 3) AS day,
+                    SUM(length(line)) + COUNT(*) * ?1
+                FROM pings GROUP BY target, day",
+            )?;
+            let rows = stmt.query_map([ROW_OVERHEAD], |row| {
+                let day = LogFile {
+                    path: PathBuf::new(),
+                    date: row.get(1)?,
+                    size: row.get::<_, i64>(2)? as u64,
+                };
+                Ok((row.get::<_, String>(0)?, day))
+            })?;
+            for day in rows {
+                days.push(day?);
+            }
+        }
+        for (target, day) in &mut days {
+            let path = Path::new(target.as_str()).join(&day.date);
+            names.insert(path.clone(), (std::mem::take(target), day.date.clone()));
+            day.path = path;
+        }
+        let oldest = retention::oldest(policy.max_age, "%y%m%d");
+        let days = days.into_iter().map(|(_, day)| day).collect();
+        let mut report = retention::plan(days, oldest.as_deref(), policy.max_size, &today);
+        report.dry_run = dry_run;
+
+        let tiers = [
+            (Tier::Hour, policy.hourly_age),
+            (Tier::Day, policy.daily_age),
+        ];
+        let mut rollups = Vec::new();
+        for (tier, max_age) in tiers {
+            let oldest = retention::oldest(max_age, tier.file_format());
+            let mut stmt = conn.prepare(
+                "SELECT target,
+                    substr(strftime(?2, time, 'unixepoch'), 3) AS period,
+                    SUM(length(record))
+                FROM rollups WHERE tier = ?1 GROUP BY target, period",
+            )?;
+            // SQLite only supports four-digit years
+            let format = tier.file_format().replace("%y", "%Y");
+            let rows = stmt.query_map(params![tier.name(), format], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)? as u64,
+                ))
+            })?;
+            for row in rows {
+                let (target, period, size) = row?;
+                if oldest
+                    .as_deref()
+                    .is_some_and(|oldest| period.as_str() < oldest)
+                {
+                    let path = Path::new(&target).join(tier.name()).join(&period);
+                    report.removed.push((path, size, Reason::Age));
+                    rollups.push((target, tier, period));
+                } else {
+                    report.kept += 1;
+                    report.kept_size += size;
+                }
+            }
+        }
+        if dry_run || report.removed.is_empty() {
+            return Ok(report);
+        }
+
+        let tx = conn.transaction()?;
+        for (path, _, _) in &report.removed {
+            let Some((target, day)) = names.get(path) else {
+                continue;
+            };
+            if let Some(start) = ping_stats::log_day(day) {
+                tx.execute(
+                    "DELETE FROM pings WHERE target = ?1 AND time >= ?2 AND time < ?3",
+                    params![target, start, Tier::Day.next(start)],
+                )?;
+            }
+        }
+        for (target, tier, period) in &rollups {
+            tx.execute(
+                "DELETE FROM rollups WHERE target = ?1 AND tier = ?2
+                    AND substr(strftime(?3, time, 'unixepoch'), 3) = ?4",
+                params![
+                    target,
+                    tier.name(),
+                    tier.file_format().replace("%y", "%Y"),
+                    period
+                ],
+            )?;
+        }
+        tx.commit()?;
+        conn.execute_batch("PRAGMA incremental_vacuum;")?;
+        Ok(report)
+    }
+}
+
+fn targets(conn: &Connection) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT DISTINCT target FROM pings")?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    rows.collect()
+}
+
+fn insert_record(
+    conn: &Connection,
+    target: &str,
+    tier: Tier,
+    record: &Record,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO rollups (target, tier, time, record) VALUES (?1, ?2, ?3, ?4)",
+        params![target, tier.name(), record.time, record.to_string()],
+    )?;
+    Ok(())
+}
+
+/// Returns the pings like [Storage::query]
+fn query(
+    conn: &Connection,
+    target: &str,
+    offset: usize,
+    count: usize,
+    start: i64,
+    end: i64,
+) -> rusqlite::Result<Vec<Ping>> {
+    let start = if start == 0 { i64::MAX } else { start };
+    let end = if end == 0 { i64::MIN } else { end };
+    let mut stmt = conn.prepare_cached(
+        "SELECT line FROM pings WHERE target = ?1 AND time < ?2 AND time >= ?3
+        ORDER BY time DESC, rowid DESC LIMIT ?4 OFFSET ?5",
+    )?;
+    let limit = i64::try_from(count).unwrap_or(-1);
+    let offset = i64::try_from(offset).unwrap_or(i64::MAX);
+    let rows = stmt.query_map(params![target, start, end, limit, offset], |row| {
+        row.get::<_, String>(0)
+    })?;
+    let mut pings = Vec::new();
+    for line in rows {
+        let line = line?;
+        match line.parse() {
+            Ok(ping) => pings.push(ping),
+            Err(_) => warn!("invalid ping of {target} in the database: {line}"),
+        }
+    }
+    Ok(pings)
+}
+
+/// Pings and rollups of a target in the database
+struct Target<'a> {
+    conn: &'a Connection,
+    target: &'a str,
+}
+
+impl Source for Target<'_> {
+    fn raw_start(&self) -> i64 {
+        let first: rusqlite::Result<Option<i64>> = self.conn.query_row(
+            "SELECT MIN(time) FROM pings WHERE target = ?1",
+            [self.target],
+            |row| row.get(0),
+        );
+        match first {
+            Ok(Some(time)) => Tier::Day.floor(time),
+            Ok(None) => i64::MAX,
+            Err(e) => {
+                error!("could not query the pings of {}: {e}", self.target);
+                i64::MAX
+            }
+        }
+    }
+
+    fn pings(&self, start: i64, end: i64) -> Vec<Ping> {
+        query(self.conn, self.target, 0, usize::MAX, end, start).unwrap_or_else(|e| {
+            error!("could not query the pings of {}: {e}", self.target);
+            Vec::new()
+        })
+    }
+
+    fn records(&self, tier: Tier) -> Vec<Record> {
+        let records = self
+            .conn
+            .prepare_cached(
+                "SELECT record FROM rollups WHERE target = ?1 AND tier = ?2 ORDER BY time",
+            )
+            .and_then(|mut stmt| {
+                let rows = stmt.query_map(params![self.target, tier.name()], |row| {
+                    row.get::<_, String>(0)
+                })?;
+                rows.collect::<rusqlite::Result<Vec<_>>>()
+            });
+        match records {
+            Ok(records) => records.iter().filter_map(|r| r.parse().ok()).collect(),
+            Err(e) => {
+                error!("could not query the rollups of {}: {e}", self.target);
+                Vec::new()
+            }
+        }
+    }
+}
+
+impl Storage for Sqlite {
+    fn append(&self, target: &str, ping: &Ping) -> io::Result<()> {
+        let conn = self.0.lock().unwrap();
+        conn.prepare_cached("INSERT INTO pings (target, time, line) VALUES (?1, ?2, ?3)")
+            .and_then(|mut stmt| stmt.execute(params![target, ping.time, ping.to_string()]))
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    fn query(&self, target: &str, offset: usize, count: usize, start: i64, end: i64) -> Vec<Ping> {
+        let conn = self.0.lock().unwrap();
+        query(&conn, target, offset, count, start, end).unwrap_or_else(|e| {
+            error!("could not query the pings of {target}: {e}");
+            Vec::new()
+        })
+    }
+
+    fn retain(&self, policy: Policy, dry_run: bool) -> Report {
+        if !dry_run {
+            if let Err(e) = self.compact() {
+                warn!("could not compact the pings: {e}");
+            }
+        }
+        let mut conn = self.0.lock().unwrap();
+        Sqlite::apply(&mut conn, policy, dry_run).unwrap_or_else(|e| {
+            warn!("could not apply the retention policy: {e}");
+            Report::default()
+        })
+    }
+
+    fn aggregate(&self, target: &str, start: i64, end: i64, width: i64, zone: Zone) -> Vec<Bucket> {
+        let conn = self.0.lock().unwrap();
+        let source = Target {
+            conn: &conn,
+            target,
+        };
+        rollup::aggregate(&source, start, end, width, zone)
+    }
+
+    fn distribution(&self, target: &str, start: i64, end: i64, bins: Bins) -> Distribution {
+        let conn = self.0.lock().unwrap();
+        let source = Target {
+            conn: &conn,
+            target,
+        };
+        rollup::distribution(&source, start, end, bins)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::fs;
+
+    use crate::ping::Outcome;
+
+    #[test]
+    fn queries() {
+        let db = Sqlite::new(Connection::open_in_memory().unwrap()).unwrap();
+        for time in [100, 160, 220, 280] {
+            db.append("a", &Ping::new(time, 1.0)).unwrap();
+        }
+        db.append("b", &Ping::lost(200, Outcome::Timeout)).unwrap();
+
+        let times = |pings: Vec<Ping>| pings.iter().map(|p| p.time).collect::<Vec<_>>();
+        assert_eq!(times(db.query("a", 0, 10, 0, 0)), [280, 220, 160, 100]);
+        assert_eq!(times(db.query("a", 1, 2, 0, 0)), [220, 160]);
+        assert_eq!(times(db.query("a", 0, 10, 280, 160)), [220, 160]);
+        assert_eq!(
+            times(db.query("a", 0, usize::MAX, i64::MAX, 161)),
+            [280, 220]
+        );
+        assert_eq!(
+            db.query("b", 0, 10, 0, 0),
+            [Ping::lost(200, Outcome::Timeout)]
+        );
+        assert!(db.query("c", 0, 10, 0, 0).is_empty());
+
+        let bins = Bins {
+            count: 1,
+            min: None,
+            max: None,
+        };
+        let distribution = db.distribution("a", 100, 280, bins);
+        assert_eq!(distribution.stats.count, 3);
+    }
+
+    #[test]
+    fn import() {
+        let dir = std::env::temp_dir().join(format!("ping-log-sqlite-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("260101.txt"), "100 1.0\n160 2.0\n").unwrap();
+        fs::write(dir.join("260102.txt"), "220 3.0\n").unwrap();
+
+        let db = Sqlite::new(Connection::open_in_memory().unwrap()).unwrap();
+        assert_eq!(db.import("1.1.1.1", &dir).unwrap(), 3);
+        // Nothing new
+        assert_eq!(db.import("1.1.1.1", &dir).unwrap(), 0);
+        let pings = db.query("1.1.1.1", 0, 10, 0, 0);
+        assert_eq!(pings, ping_stats::read_log(&dir, 0, 10, 0, 0));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn compaction() {
+        let db = Sqlite::new(Connection::open_in_memory().unwrap()).unwrap();
+        let start = Tier::Day.floor(Utc::now().timestamp()) - 20 * 86400;
+        // Several ranges with a gap between them
+        for day in (0..10).chain(15..18) {
+            let time = start + day * 86400 + 60;
+            db.append("a", &Ping::new(time, 1.0)).unwrap();
+            db.append("a", &Ping::new(time + 3600, 2.0)).unwrap();
+        }
+        assert_eq!(db.compact().unwrap(), 13 * 3);
+        assert_eq!(db.compact().unwrap(), 0);
+
+        let conn = db.0.lock().unwrap();
+        let source = Target {
+            conn: &conn,
+            target: "a",
+        };
+        let daily = source.records(Tier::Day);
+        assert_eq!(daily.len(), 13);
+        assert!(daily.iter().all(|r| r.count == 2));
+        assert_eq!(source.records(Tier::Hour).len(), 26);
+    }
+
+    #[test]
+    fn retention() {
+        let db = Sqlite::new(Connection::open_in_memory().unwrap()).unwrap();
+        let now = Utc::now().timestamp();
+        let old = now - 40 * 86400;
+        for time in [old, old + 60, now - 2 * 86400, now] {
+            db.append("a", &Ping::new(time, 1.0)).unwrap();
+        }
+        let policy = Policy {
+            max_age: Some(30),
+            max_size: None,
+            hourly_age: None,
+            daily_age: None,
+        };
+
+        let report = db.retain(policy, true);
+        assert_eq!(report.removed.len(), 1);
+        assert_eq!(report.kept, 2);
+        assert_eq!(db.query("a", 0, 10, 0, 0).len(), 4);
+
+        let report = db.retain(policy, false);
+        assert_eq!(report.removed.len(), 1);
+        assert_eq!(db.query("a", 0, 10, 0, 0).len(), 2);
+        let hourly = |db: &Sqlite| {
+            let conn = db.0.lock().unwrap();
+            let source = Target {
+                conn: &conn,
+                target: "a",
+            };
+            source.records(Tier::Hour).len()
+        };
+        let before = hourly(&db);
+
+        // Hourly rollups of the old month are removed as well
+        let policy = Policy {
+            hourly_age: Some(1),
+            ..policy
+        };
+        db.retain(policy, false);
+        assert!(hourly(&db) < before);
+        assert_eq!(db.query("a", 0, 10, 0, 0).len(), 2);
+
+        // The removed pings are served from the rollups
+        let buckets = db.aggregate("a", old - 86400, now, 86400 * 30, Zone::Local);
+        assert_eq!(buckets.iter().map(|b| b.count).sum::<usize>(), 3);
+        let conn = db.0.lock().unwrap();
+        let source = Target {
+            conn: &conn,
+            target: "a",
+        };
+        assert_eq!(source.records(Tier::Day).len(), 2);
+    }
+}
@@ -0,0 +1,88 @@
+//! Persistence of the pings.
+//!
+//! By default, the pings are logged into a text file per target and day
+//! (`<log dir>/<target>/<yymmdd>.txt`). Alternatively, they are stored in an
+//! SQLite database (see [super::sqlite]).
+
+use std::io;
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+
+use super::ping::Ping;
+use super::ping_aggregate::{Bins, Bucket, Distribution, Zone};
+use super::ping_stats;
+use super::retention::{self, Policy, Report};
+use super::rollup::{self, Dir};
+
+/// Storage of the pings of all targets
+pub trait Storage: Send + Sync {
+    /// Appends the ping of the target
+    fn append(&self, target: &str, ping: &Ping) -> io::Result<()>;
+
+    /// Returns the pings of the target from the newest to the oldest,
+    /// skipping the ones at or after `start` and stopping before `end`
+    /// (0 for unbounded)
+    fn query(&self, target: &str, offset: usize, count: usize, start: i64, end: i64) -> Vec<Ping>;
+
+    /// Compacts the pings into rollups and removes the ones exceeding the
+    /// policy. With `dry_run`, nothing is changed.
+    fn retain(&self, policy: Policy, dry_run: bool) -> Report;
+
+    /// Aggregates the pings between `start` (inclusive) and `end` (exclusive)
+    /// into buckets of `width` seconds (see [rollup::aggregate])
+    fn aggregate(&self, target: &str, start: i64, end: i64, width: i64, zone: Zone) -> Vec<Bucket>;
+
+    /// Computes the response time distribution between `start` (inclusive)
+    /// and `end` (exclusive) (see [rollup::distribution])
+    fn distribution(&self, target: &str, start: i64, end: i64, bins: Bins) -> Distribution;
+}
+
+/// Available storage implementations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    /// Text file per target and day
+    Files,
+    /// SQLite database
+    Sqlite,
+}
+
+/// Text files in the log directory
+pub struct Files {
+    log_dir: PathBuf,
+}
+
+impl Files {
+    pub fn new(log_dir: PathBuf) -> Files {
+        Files { log_dir }
+    }
+
+    fn dir(&self, target: &str) -> PathBuf {
+        ping_stats::target_dir(&self.log_dir, target)
+    }
+}
+
+impl Storage for Files {
+    fn append(&self, target: &str, ping: &Ping) -> io::Result<()> {
+        ping_stats::write_log(&self.dir(target), ping)
+    }
+
+    fn query(&self, target: &str, offset: usize, count: usize, start: i64, end: i64) -> Vec<Ping> {
+        ping_stats::read_log(self.dir(target), offset, count, start, end)
+    }
+
+    fn retain(&self, policy: Policy, dry_run: bool) -> Report {
+        if !dry_run {
+            retention::compact(&self.log_dir);
+        }
+        retention::apply(&self.log_dir, policy, dry_run)
+    }
+
+    fn aggregate(&self, target: &str, start: i64, end: i64, width: i64, zone: Zone) -> Vec<Bucket> {
+        rollup::aggregate(&Dir(&self.dir(target)), start, end, width, zone)
+    }
+
+    fn distribution(&self, target: &str, start: i64, end: i64, bins: Bins) -> Distribution {
+        rollup::distribution(&Dir(&self.dir(target)), start, end, bins)
+    }
+}
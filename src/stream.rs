@@ -0,0 +1,291 @@
+//! Live updates for the dashboard.
+//!
+//! The monitors publish their logged pings and the hardware and Minecraft
+//! status to a bounded broadcast channel. Slow clients never block the
+//! monitors: if they fall behind, the dropped pings are read from the
+//! storage instead, which is also how reconnecting clients are resumed.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+use super::alert::Alert;
+use super::outage::Outage;
+use super::ping::Ping;
+use super::storage::Storage;
+use super::{hw, mc};
+
+/// Number of updates buffered for each client
+pub const CAPACITY: usize = 256;
+/// Maximum number of pings per target that are replayed at once
+const MAX_REPLAY: usize = 3600;
+/// Initial time range of the replayed chunks in seconds
+const REPLAY_CHUNK: i64 = 60 * 60;
+
+#[derive(Debug, Clone)]
+pub enum Update {
+    /// A ping has been logged for the target
+    Ping(String, Ping),
+    Hw(hw::Status),
+    Mc(Vec<mc::Status>),
+    /// An alert has fired or resolved
+    Alert(Alert),
+    /// An outage has started or ended
+    Outage(Outage),
+}
+
+/// Distributes the updates to the connected clients
+#[derive(Debug)]
+pub struct Hub(broadcast::Sender<Update>);
+
+impl Hub {
+    /// Creates a hub that buffers up to `capacity` updates per client
+    pub fn new(capacity: usize) -> Hub {
+        Hub(broadcast::channel(capacity).0)
+    }
+
+    pub fn publish(&self, update: Update) {
+        // Fails only if no client is connected
+        let _ = self.0.send(update);
+    }
+
+    /// Returns a receiver of all updates, without catching up from the storage
+    pub fn receiver(&self) -> broadcast::Receiver<Update> {
+        self.0.subscribe()
+    }
+
+    /// Subscribes to the pings of the given targets that are logged after
+    /// `since`, or from now on if `since` is 0.
+    pub fn subscribe(&self, storage: Arc<dyn Storage>, targets: Vec<String>, since: i64) -> Client {
+        // Subscribe before reading the logs, duplicates are filtered later
+        let receiver = self.0.subscribe();
+        let now = chrono::Local::now().timestamp();
+        let last = vec![if since > 0 { since } else { now }; targets.len()];
+        let mut client = Client {
+            receiver,
+            storage,
+            targets,
+            last,
+            pending: VecDeque::new(),
+            replayed: since,
+            replay_end: since,
+            chunk: REPLAY_CHUNK,
+        };
+        if since > 0 {
+            client.replay(now);
+        }
+        client
+    }
+}
+
+/// Subscription of a single client
+pub struct Client {
+    receiver: broadcast::Receiver<Update>,
+    storage: Arc<dyn Storage>,
+    targets: Vec<String>,
+    /// Time of the last ping sent for each target
+    last: Vec<i64>,
+    /// Pings read from the logs that have not been sent yet
+    pending: VecDeque<Update>,
+    /// Time up to which the logged pings have been read
+    replayed: i64,
+    /// Time up to which the logged pings have to be read to catch up
+    replay_end: i64,
+    /// Time range of the next chunk that is read
+    chunk: i64,
+}
+
+impl Client {
+    /// Returns the next update, or `None` if the hub has been dropped
+    pub async fn next(&mut self) -> Option<Update> {
+        loop {
+            if let Some(update) = self.pending.pop_front() {
+                return Some(update);
+            }
+            if self.replayed < self.replay_end {
+                self.read_chunk();
+                continue;
+            }
+            match self.receiver.recv().await {
+                Ok(Update::Ping(target, ping)) => {
+                    let Some(i) = self.targets.iter().position(|t| *t == target) else {
+                        continue;
+                    };
+                    if ping.time > self.last[i] {
+                        self.last[i] = ping.time;
+                        return Some(Update::Ping(target, ping));
+                    }
+                }
+                Ok(update) => return Some(update),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("stream client skipped {skipped} updates, catching up from the logs");
+                    self.replay(chrono::Local::now().timestamp());
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Catches up with the logged pings that are newer than the last sent
+    /// ones, up to `until`
+    fn replay(&mut self, until: i64) {
+        if self.replayed >= self.replay_end {
+            self.replayed = self.last.iter().copied().min().unwrap_or(until);
+        }
+        self.replay_end = until;
+    }
+
+    /// Queues the logged pings of the next chunk, from the oldest to the newest
+    ///
+    /// The chunk grows over gaps in the logs and shrinks until it holds at
+    /// most `MAX_REPLAY` pings per target.
+    fn read_chunk(&mut self) {
+        let (end, pings) = loop {
+            let end = self
+                .replayed
+                .saturating_add(self.chunk)
+                .min(self.replay_end);
+            let mut pings = Vec::new();
+            let mut full = false;
+            for target in &self.targets {
+                let missed = self
+                    .storage
+                    .query(target, 0, MAX_REPLAY, end + 1, self.replayed + 1);
+                full |= missed.len() >= MAX_REPLAY;
+                pings.push(missed);
+            }
+            if full && self.chunk > 1 {
+                self.chunk /= 2;
+            } else {
+                break (end, pings);
+            }
+        };
+        if pings.iter().all(Vec::is_empty) {
+            self.chunk = self.chunk.saturating_mul(2);
+        }
+        self.replayed = end;
+
+        let mut queued = Vec::new();
+        for ((target, last), missed) in self.targets.iter().zip(&mut self.last).zip(pings) {
+            let missed: Vec<_> = missed.into_iter().filter(|p| p.time > *last).collect();
+            if let Some(newest) = missed.first() {
+                *last = newest.time;
+            }
+            queued.extend(missed.into_iter().map(|p| (target.clone(), p)));
+        }
+        queued.sort_by_key(|(_, p)| p.time);
+        self.pending
+            .extend(queued.into_iter().map(|(t, p)| Update::Ping(t, p)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::fs;
+
+    use crate::storage::Files;
+
+    /// 2026-01-01 00:00 UTC
+    const T: i64 = 1767225600;
+
+    /// Log lines of the pings at the seconds after `T`
+    fn log(times: &[i64], ping: i64) -> String {
+        times
+            .iter()
+            .map(|t| format!("{} {ping}\n", T + t))
+            .collect()
+    }
+
+    fn times(updates: &[Update]) -> Vec<(&str, i64)> {
+        updates
+            .iter()
+            .filter_map(|u| match u {
+                Update::Ping(t, p) => Some((t.as_str(), p.time)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn resume() {
+        let dir = std::env::temp_dir().join(format!("ping-log-stream-{}", std::process::id()));
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(&b).unwrap();
+        fs::write(a.join("260101.txt"), log(&[100, 160, 220], 1)).unwrap();
+        fs::write(b.join("260101.txt"), log(&[130, 190], 2)).unwrap();
+
+        let hub = Hub::new(2);
+        let storage = Arc::new(Files::new(dir.clone()));
+        let mut client = hub.subscribe(storage, vec!["a".into(), "b".into()], T + 130);
+
+        // Already replayed from the logs
+        hub.publish(Update::Ping("a".into(), Ping::new(T + 220, 1.0)));
+        // Not subscribed
+        hub.publish(Update::Ping("c".into(), Ping::new(T + 230, 1.0)));
+        let mut updates = Vec::new();
+        for _ in 0..3 {
+            updates.push(client.next().await.unwrap());
+        }
+        assert_eq!(
+            times(&updates),
+            [("a", T + 160), ("b", T + 190), ("a", T + 220)]
+        );
+
+        hub.publish(Update::Hw(hw::Status::default()));
+        assert!(matches!(client.next().await, Some(Update::Hw(_))));
+
+        // Overflow the buffer, the missed pings are read from the logs
+        fs::write(a.join("260101.txt"), log(&[100, 160, 220, 280, 340], 1)).unwrap();
+        fs::write(b.join("260101.txt"), log(&[130, 190, 250], 2)).unwrap();
+        hub.publish(Update::Ping("a".into(), Ping::new(T + 280, 1.0)));
+        hub.publish(Update::Ping("b".into(), Ping::new(T + 250, 2.0)));
+        hub.publish(Update::Ping("a".into(), Ping::new(T + 340, 1.0)));
+        let mut updates = Vec::new();
+        for _ in 0..3 {
+            updates.push(client.next().await.unwrap());
+        }
+        assert_eq!(
+            times(&updates),
+            [("b", T + 250), ("a", T + 280), ("a", T + 340)]
+        );
+
+        drop(hub);
+        assert!(client.next().await.is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replay_chunks() {
+        let dir = std::env::temp_dir().join(format!("ping-log-chunks-{}", std::process::id()));
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(&b).unwrap();
+        // More pings than are replayed at once, and a gap of a day
+        let seconds: Vec<i64> = (0..2 * MAX_REPLAY as i64).collect();
+        fs::write(a.join("260101.txt"), log(&seconds, 1)).unwrap();
+        fs::write(b.join("260102.txt"), log(&[86400 + 60], 2)).unwrap();
+
+        let hub = Hub::new(2);
+        let storage = Arc::new(Files::new(dir.clone()));
+        let mut client = hub.subscribe(storage, vec!["a".into(), "b".into()], T);
+        let mut updates = Vec::new();
+        for _ in 0..seconds.len() {
+            updates.push(client.next().await.unwrap());
+        }
+        let expected: Vec<_> = seconds[1..]
+            .iter()
+            .map(|s| ("a", T + s))
+            .chain([("b", T + 86400 + 60)])
+            .collect();
+        assert_eq!(times(&updates), expected);
+
+        drop(hub);
+        assert!(client.next().await.is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,329 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::dns;
+
+/// Monitored ping target
+///
+/// Plain hosts are pinged with ICMP echo requests, other probes are selected
+/// by the url scheme (e.g. `tcp://example.com:443`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// ICMP echo requests to a host
+    Icmp(String),
+    /// TCP connect to a host and port
+    Tcp { host: String, port: u16 },
+    /// HTTP(S) GET request
+    Http(Http),
+    /// DNS query to a specific resolver
+    Dns(Dns),
+}
+
+/// DNS probe target
+///
+/// The format is `dns://<resolver>[:<port>]/<name>[?type=<record type>]`,
+/// e.g. `dns://1.1.1.1/example.com?type=AAAA`. The default type is `A`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dns {
+    /// The url as given by the user
+    pub url: String,
+    pub resolver: String,
+    pub port: u16,
+    /// Queried domain name
+    pub name: String,
+    pub record_type: u16,
+}
+
+impl FromStr for Dns {
+    type Err = String;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let usage =
+            || format!("expected dns://<resolver>[:<port>]/<name>[?type=<type>], got '{url}'");
+        let rest = url.strip_prefix("dns://").ok_or_else(usage)?;
+        let (authority, query) = rest.split_once('/').ok_or_else(usage)?;
+        let (name, params) = query.split_once('?').unwrap_or((query, ""));
+
+        let (resolver, port) = if authority.ends_with(']') || !authority.contains(':') {
+            let host = authority.trim_start_matches('[').trim_end_matches(']');
+            (host.into(), 53)
+        } else {
+            split_port(authority).ok_or_else(usage)?
+        };
+        if resolver.is_empty() || name.is_empty() {
+            return Err(usage());
+        }
+
+        let mut record_type = 1;
+        for param in params.split('&').filter(|p| !p.is_empty()) {
+            match param.split_once('=') {
+                Some(("type", ty)) => {
+                    record_type = dns::record_type(ty)
+                        .ok_or_else(|| format!("unknown record type '{ty}' in '{url}'"))?
+                }
+                _ => return Err(format!("unknown parameter '{param}' in '{url}'")),
+            }
+        }
+
+        Ok(Dns {
+            url: url.into(),
+            resolver,
+            port,
+            name: name.into(),
+            record_type,
+        })
+    }
+}
+
+/// HTTP(S) probe target
+///
+/// The expected response can be configured with the url fragment, which is
+/// not sent to the server, e.g. `https://example.com/health#status=200&body=ok`.
+/// Without expectations, every status below 400 is a success.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Http {
+    /// The url as given by the user (including the fragment)
+    pub url: String,
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    /// Path and query of the request
+    pub path: String,
+    pub expect_status: Option<u16>,
+    pub expect_body: Option<String>,
+}
+
+impl FromStr for Http {
+    type Err = String;
+
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(format!(
+                "expected http(s)://<host>[:<port>][/<path>], got '{url}'"
+            ));
+        };
+
+        let (rest, fragment) = rest.split_once('#').unwrap_or((rest, ""));
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let path = if path.starts_with('?') {
+            format!("/{path}")
+        } else {
+            path.into()
+        };
+
+        let default_port = if tls { 443 } else { 80 };
+        let (host, port) = if authority.ends_with(']') || !authority.contains(':') {
+            let host = authority.trim_start_matches('[').trim_end_matches(']');
+            (host.into(), default_port)
+        } else {
+            split_port(authority).ok_or_else(|| format!("invalid host in '{url}'"))?
+        };
+        if host.is_empty() {
+            return Err(format!("missing host in '{url}'"));
+        }
+
+        let mut expect_status = None;
+        let mut expect_body = None;
+        for param in fragment.split('&').filter(|p| !p.is_empty()) {
+            match param.split_once('=') {
+                Some(("status", status)) => {
+                    expect_status = Some(
+                        status
+                            .parse()
+                            .map_err(|_| format!("invalid status in '{url}'"))?,
+                    )
+                }
+                Some(("body", body)) => expect_body = Some(body.into()),
+                _ => return Err(format!("unknown expectation '{param}' in '{url}'")),
+            }
+        }
+
+        Ok(Http {
+            url: url.into(),
+            tls,
+            host,
+            port,
+            path,
+            expect_status,
+            expect_body,
+        })
+    }
+}
+
+impl Target {
+    /// Returns the host that is probed (the resolver for DNS targets)
+    pub fn host(&self) -> &str {
+        match self {
+            Target::Icmp(host) | Target::Tcp { host, .. } => host,
+            Target::Http(http) => &http.host,
+            Target::Dns(dns) => &dns.resolver,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Icmp(host) => write!(f, "{host}"),
+            Target::Tcp { host, port } if host.contains(':') => {
+                write!(f, "tcp://[{host}]:{port}")
+            }
+            Target::Tcp { host, port } => write!(f, "tcp://{host}:{port}"),
+            Target::Http(http) => write!(f, "{}", http.url),
+            Target::Dns(dns) => write!(f, "{}", dns.url),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(addr) = s.strip_prefix("tcp://") {
+            let (host, port) = split_port(addr)
+                .ok_or_else(|| format!("expected tcp://<host>:<port>, got '{s}'"))?;
+            Ok(Target::Tcp { host, port })
+        } else if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(Target::Http(s.parse()?))
+        } else if s.starts_with("dns://") {
+            Ok(Target::Dns(s.parse()?))
+        } else if s.contains("://") {
+            Err(format!("unsupported target '{s}'"))
+        } else if s.is_empty() {
+            Err("empty target".into())
+        } else {
+            Ok(Target::Icmp(s.into()))
+        }
+    }
+}
+
+/// Splits `host:port` or `[ipv6]:port`
+fn split_port(addr: &str) -> Option<(String, u16)> {
+    let (host, port) = addr.trim_end_matches('/').rsplit_once(':')?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    if host.is_empty() {
+        return None;
+    }
+    Some((host.into(), port.parse().ok()?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!("1.1.1.1".parse(), Ok(Target::Icmp("1.1.1.1".into())));
+        assert_eq!("::1".parse(), Ok(Target::Icmp("::1".into())));
+        assert_eq!(
+            "tcp://example.com:443".parse(),
+            Ok(Target::Tcp {
+                host: "example.com".into(),
+                port: 443
+            })
+        );
+        assert_eq!(
+            "tcp://[::1]:22".parse(),
+            Ok(Target::Tcp {
+                host: "::1".into(),
+                port: 22
+            })
+        );
+        assert!("tcp://example.com".parse::<Target>().is_err());
+        assert!("tcp://:80".parse::<Target>().is_err());
+        assert!("ftp://example.com".parse::<Target>().is_err());
+        assert!("".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn parse_http() {
+        let Ok(Target::Http(http)) = "https://example.com".parse() else {
+            panic!("no http target");
+        };
+        assert!(http.tls);
+        assert_eq!((http.host.as_str(), http.port), ("example.com", 443));
+        assert_eq!(http.path, "/");
+        assert_eq!((http.expect_status, http.expect_body), (None, None));
+
+        let Ok(Target::Http(http)) = "http://[::1]:8080/health?full=1#status=204&body=ok".parse()
+        else {
+            panic!("no http target");
+        };
+        assert!(!http.tls);
+        assert_eq!((http.host.as_str(), http.port), ("::1", 8080));
+        assert_eq!(http.path, "/health?full=1");
+        assert_eq!(http.expect_status, Some(204));
+        assert_eq!(http.expect_body.as_deref(), Some("ok"));
+
+        let Ok(Target::Http(http)) = "http://localhost?x=1".parse() else {
+            panic!("no http target");
+        };
+        assert_eq!((http.host.as_str(), http.port), ("localhost", 80));
+        assert_eq!(http.path, "/?x=1");
+
+        assert!("http://".parse::<Target>().is_err());
+        assert!("http://host:x/".parse::<Target>().is_err());
+        assert!("http://host/#status=abc".parse::<Target>().is_err());
+        assert!("http://host/#other=1".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn parse_dns() {
+        assert_eq!(
+            "dns://1.1.1.1/example.com".parse(),
+            Ok(Target::Dns(Dns {
+                url: "dns://1.1.1.1/example.com".into(),
+                resolver: "1.1.1.1".into(),
+                port: 53,
+                name: "example.com".into(),
+                record_type: 1,
+            }))
+        );
+        let Ok(Target::Dns(dns)) = "dns://[::1]:5353/example.com?type=aaaa".parse() else {
+            panic!("no dns target");
+        };
+        assert_eq!((dns.resolver.as_str(), dns.port), ("::1", 5353));
+        assert_eq!(dns.record_type, 28);
+
+        assert!("dns://1.1.1.1".parse::<Target>().is_err());
+        assert!("dns://1.1.1.1/".parse::<Target>().is_err());
+        assert!("dns://1.1.1.1/example.com?type=XYZ"
+            .parse::<Target>()
+            .is_err());
+    }
+
+    #[test]
+    fn display() {
+        for target in [
+            "1.1.1.1",
+            "tcp://example.com:443",
+            "tcp://[::1]:22",
+            "https://example.com/health#status=200",
+            "dns://1.1.1.1/example.com?type=MX",
+        ] {
+            assert_eq!(target.parse::<Target>().unwrap().to_string(), target);
+        }
+    }
+
+    #[test]
+    fn host() {
+        for (target, host) in [
+            ("1.1.1.1", "1.1.1.1"),
+            ("tcp://[::1]:22", "::1"),
+            ("https://example.com:8443/health", "example.com"),
+            ("dns://9.9.9.9/example.com", "9.9.9.9"),
+        ] {
+            assert_eq!(target.parse::<Target>().unwrap().host(), host);
+        }
+    }
+}
@@ -0,0 +1,392 @@
+//! Path traces (traceroute) to the ping targets.
+//!
+//! Echo requests with increasing ttl are sent to the target and the routers
+//! that report the exceeded ttl are recorded as hops.
+//! The traces are stored as `<log dir>/traces/<timestamp>.txt`. Tracing needs
+//! the ICMP sockets of Linux, the stored traces are read on all platforms.
+
+use std::fmt;
+use std::fs::{self, read_dir};
+use std::io::{ErrorKind, Result};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+
+use serde::Serialize;
+use tracing::{error, warn};
+
+#[cfg(target_os = "linux")]
+use super::icmp::{IcmpError, Response, Socket};
+use super::ping::Ping;
+use super::ping_request::resolve;
+
+/// Maximum number of hops to the target
+#[cfg(target_os = "linux")]
+const MAX_HOPS: u8 = 30;
+/// Echo requests per hop
+#[cfg(target_os = "linux")]
+const PROBES: u8 = 3;
+/// Time to wait for the response of a router
+#[cfg(target_os = "linux")]
+const TIMEOUT: Duration = Duration::from_secs(1);
+/// Give up after this many consecutive hops without any response
+#[cfg(target_os = "linux")]
+const MAX_SILENT: u8 = 5;
+/// Number of traces that are kept per target
+const MAX_TRACES: usize = 256;
+
+/// Cause of a trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reason {
+    /// Some or all probes of an interval were lost
+    Loss,
+    /// The response time exceeded the threshold
+    Latency,
+}
+
+impl Reason {
+    /// Returns the reason for tracing the path after the given ping if any
+    pub fn of(ping: &Ping, threshold: Option<f64>) -> Option<Reason> {
+        if ping.loss() > 0.0 {
+            Some(Reason::Loss)
+        } else if threshold.is_some_and(|t| ping.ping > t) {
+            Some(Reason::Latency)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Reason::Loss => "loss",
+            Reason::Latency => "latency",
+        })
+    }
+}
+
+impl FromStr for Reason {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "loss" => Ok(Reason::Loss),
+            "latency" => Ok(Reason::Latency),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Router (or the target) at a distance of `ttl` hops
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Hop {
+    pub ttl: u8,
+    /// Address of the responding router, `None` if it did not respond
+    pub addr: Option<IpAddr>,
+    /// Response times in ms of the answered probes
+    pub rtts: Vec<f64>,
+}
+
+/// Snapshot of the path to a target
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Trace {
+    pub time: i64,
+    pub reason: Reason,
+    pub destination: IpAddr,
+    pub hops: Vec<Hop>,
+}
+
+/// The first line contains `<time> <reason> <destination>`, followed by
+/// a line `<ttl> <addr> <rtts..>` per hop (`*` for a missing address).
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {} {}", self.time, self.reason, self.destination)?;
+        for hop in &self.hops {
+            write!(f, "{}", hop.ttl)?;
+            match hop.addr {
+                Some(addr) => write!(f, " {addr}")?,
+                None => write!(f, " *")?,
+            }
+            for rtt in &hop.rtts {
+                write!(f, " {rtt}")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Trace {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut lines = s.lines();
+        let mut header = lines.next().ok_or(())?.split_whitespace();
+        let time = header.next().ok_or(())?.parse().map_err(|_| ())?;
+        let reason = header.next().ok_or(())?.parse()?;
+        let destination = header.next().ok_or(())?.parse().map_err(|_| ())?;
+
+        let hops = lines
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let mut parts = line.split_whitespace();
+                let ttl = parts.next().ok_or(())?.parse().map_err(|_| ())?;
+                let addr = match parts.next().ok_or(())? {
+                    "*" => None,
+                    addr => Some(addr.parse().map_err(|_| ())?),
+                };
+                let rtts = parts
+                    .map(|rtt| rtt.parse().map_err(|_| ()))
+                    .collect::<std::result::Result<_, _>>()?;
+                Ok(Hop { ttl, addr, rtts })
+            })
+            .collect::<std::result::Result<_, _>>()?;
+
+        Ok(Trace {
+            time,
+            reason,
+            destination,
+            hops,
+        })
+    }
+}
+
+/// Traces the path to `addr` with ICMP echo requests of increasing ttl.
+///
+/// This takes until the target responded or [`MAX_HOPS`] are reached.
+#[cfg(target_os = "linux")]
+pub async fn trace(addr: IpAddr) -> Result<Vec<Hop>> {
+    let socket = Socket::new(addr.is_ipv6())?;
+    let mut hops = Vec::new();
+    let mut seq = 0u16;
+    let mut silent = 0;
+
+    for ttl in 1..=MAX_HOPS {
+        socket.set_ttl(ttl)?;
+        let mut hop = Hop {
+            ttl,
+            addr: None,
+            rtts: Vec::new(),
+        };
+        let mut done = false;
+        for _ in 0..PROBES {
+            seq = seq.wrapping_add(1);
+            let (from, rtt) = match socket.send(addr, seq, TIMEOUT).await {
+                Ok(Response::Reply(reply)) => {
+                    done = true;
+                    (addr, reply.rtt)
+                }
+                Ok(Response::Error { from, error, rtt }) => {
+                    done |= error == IcmpError::Unreachable || from == addr;
+                    (from, rtt)
+                }
+                Err(e) if e.kind() == ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e),
+            };
+            hop.addr.get_or_insert(from);
+            hop.rtts.push((rtt.as_secs_f64() * 10000.0).round() / 10.0);
+        }
+
+        silent = if hop.addr.is_some() { 0 } else { silent + 1 };
+        hops.push(hop);
+        if done || silent >= MAX_SILENT {
+            break;
+        }
+    }
+    Ok(hops)
+}
+
+/// Tracing is not supported without the ICMP sockets of Linux
+#[cfg(not(target_os = "linux"))]
+pub async fn trace(_addr: IpAddr) -> Result<Vec<Hop>> {
+    Err(ErrorKind::Unsupported.into())
+}
+
+/// Traces the path to the `host` and stores the snapshot in the log dir.
+///
+/// Failures are only logged, as the traces are a diagnostic aid.
+pub async fn snapshot(host: &str, log_dir: &Path, time: i64, reason: Reason) {
+    let destination = match resolve(host).await {
+        Ok(addr) => addr,
+        Err(e) => {
+            warn!("could not resolve {host} for tracing: {e}");
+            return;
+        }
+    };
+    let hops = match trace(destination).await {
+        Ok(hops) => hops,
+        Err(e) => {
+            warn!("trace to {host} failed: {e}");
+            return;
+        }
+    };
+    let trace = Trace {
+        time,
+        reason,
+        destination,
+        hops,
+    };
+    if let Err(e) = write_trace(log_dir, &trace) {
+        error!("could not write trace to {host}: {e}");
+    }
+}
+
+fn trace_dir(log_dir: &Path) -> PathBuf {
+    log_dir.join("traces")
+}
+
+/// Stores the trace and removes the oldest if there are more than
+/// [`MAX_TRACES`].
+fn write_trace(log_dir: &Path, trace: &Trace) -> Result<()> {
+    let dir = trace_dir(log_dir);
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join(format!("{}.txt", trace.time)), trace.to_string())?;
+
+    let times = trace_times(&dir);
+    for time in &times[..times.len().saturating_sub(MAX_TRACES)] {
+        fs::remove_file(dir.join(format!("{time}.txt")))?;
+    }
+    Ok(())
+}
+
+/// Returns the timestamps of the stored traces in ascending order
+fn trace_times(dir: &Path) -> Vec<i64> {
+    let Ok(entries) = read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut times: Vec<i64> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name();
+            name.to_str()?.strip_suffix(".txt")?.parse().ok()
+        })
+        .collect();
+    times.sort_unstable();
+    times
+}
+
+/// Returns the stored traces for the given range, beginning with the newest.
+///
+/// Like for the pings, `start` has to be larger (after) than `end`.
+pub fn read_traces(
+    log_dir: &Path,
+    offset: usize,
+    count: usize,
+    start: i64,
+    end: i64,
+) -> Vec<Trace> {
+    let dir = trace_dir(log_dir);
+    trace_times(&dir)
+        .into_iter()
+        .rev()
+        .skip_while(|&time| start != 0 && time >= start)
+        .take_while(|&time| end == 0 || time >= end)
+        .skip(offset)
+        .take(count)
+        .filter_map(|time| {
+            let input = fs::read_to_string(dir.join(format!("{time}.txt"))).ok()?;
+            input.parse().ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ping::{Burst, Outcome};
+
+    fn example(time: i64) -> Trace {
+        Trace {
+            time,
+            reason: Reason::Loss,
+            destination: [1, 1, 1, 1].into(),
+            hops: vec![
+                Hop {
+                    ttl: 1,
+                    addr: Some([192, 168, 0, 1].into()),
+                    rtts: vec![0.4, 0.5, 0.3],
+                },
+                Hop {
+                    ttl: 2,
+                    addr: None,
+                    rtts: vec![],
+                },
+                Hop {
+                    ttl: 3,
+                    addr: Some([1, 1, 1, 1].into()),
+                    rtts: vec![11.2],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn format() {
+        let trace = example(1626457680);
+        let text = trace.to_string();
+        assert_eq!(
+            text,
+            "1626457680 loss 1.1.1.1\n1 192.168.0.1 0.4 0.5 0.3\n2 *\n3 1.1.1.1 11.2\n"
+        );
+        assert_eq!(text.parse(), Ok(trace));
+        assert_eq!("1626457680 other 1.1.1.1\n".parse::<Trace>(), Err(()));
+        assert_eq!("1626457680 loss 1.1.1.1\nx *\n".parse::<Trace>(), Err(()));
+    }
+
+    #[test]
+    fn reason() {
+        let ping = Ping::new(0, 20.0);
+        assert_eq!(Reason::of(&ping, None), None);
+        assert_eq!(Reason::of(&ping, Some(30.0)), None);
+        assert_eq!(Reason::of(&ping, Some(10.0)), Some(Reason::Latency));
+        let lost = Ping::lost(0, Outcome::Timeout);
+        assert_eq!(Reason::of(&lost, None), Some(Reason::Loss));
+        let partial = Ping {
+            burst: Some(Burst::new(3, &[20.0, 20.0], 0.0)),
+            ..ping
+        };
+        assert_eq!(Reason::of(&partial, None), Some(Reason::Loss));
+    }
+
+    #[test]
+    fn storage() {
+        let dir = std::env::temp_dir().join(format!("ping-log-traces-{}", std::process::id()));
+        for time in 0..MAX_TRACES as i64 + 2 {
+            write_trace(&dir, &example(time)).unwrap();
+        }
+        assert_eq!(trace_times(&trace_dir(&dir)).len(), MAX_TRACES);
+
+        let newest = MAX_TRACES as i64 + 1;
+        let traces = read_traces(&dir, 0, 3, 0, 0);
+        let times: Vec<_> = traces.iter().map(|t| t.time).collect();
+        assert_eq!(times, [newest, newest - 1, newest - 2]);
+        assert_eq!(traces[0], example(newest));
+
+        let times: Vec<_> = read_traces(&dir, 1, 10, 100, 97)
+            .iter()
+            .map(|t| t.time)
+            .collect();
+        assert_eq!(times, [98, 97]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn trace_localhost() {
+        let localhost = [127, 0, 0, 1].into();
+        match trace(localhost).await {
+            Ok(hops) => {
+                assert_eq!(hops.len(), 1);
+                assert_eq!(hops[0].addr, Some(localhost));
+                assert_eq!(hops[0].rtts.len(), PROBES as usize);
+            }
+            // ICMP sockets might not be permitted
+            Err(e) => assert_eq!(e.kind(), ErrorKind::PermissionDenied),
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Verification and repair of the log files.
+//!
+//! Corrupt lines (e.g. half-written on a power cut) are skipped when the logs
+//! are read. They are reported by the `verify` and removed by the `repair`
+//! subcommand.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use super::ping_stats;
+use super::retention;
+
+/// Maximum number of corrupt line numbers that are printed per file
+const MAX_LISTED: usize = 10;
+
+/// Corrupt lines of a log file
+#[derive(Debug, PartialEq)]
+pub struct Damage {
+    pub path: PathBuf,
+    /// Total number of lines
+    pub lines: usize,
+    /// Numbers of the corrupt lines (starting at 1)
+    pub corrupt: Vec<usize>,
+    /// The last line has no line break, so the next ping would be appended to it
+    pub unterminated: bool,
+}
+
+/// Result of a verification
+#[derive(Debug, Default)]
+pub struct Report {
+    pub repair: bool,
+    /// Number of checked logs
+    pub files: usize,
+    pub damaged: Vec<Damage>,
+    /// Logs that could not be read or repaired
+    pub errors: Vec<(PathBuf, io::Error)>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for damage in &self.damaged {
+            write!(f, "{}:", damage.path.display())?;
+            if !damage.corrupt.is_empty() {
+                let listed: Vec<_> = damage
+                    .corrupt
+                    .iter()
+                    .take(MAX_LISTED)
+                    .map(usize::to_string)
+                    .collect();
+                let more = if damage.corrupt.len() > MAX_LISTED {
+                    ", .."
+                } else {
+                    ""
+                };
+                write!(
+                    f,
+                    " {} of {} lines corrupt (line {}{more})",
+                    damage.corrupt.len(),
+                    damage.lines,
+                    listed.join(", ")
+                )?;
+            }
+            if damage.unterminated {
+                let sep = if damage.corrupt.is_empty() { "" } else { "," };
+                write!(f, "{sep} missing final line break")?;
+            }
+            writeln!(f, "{}", if self.repair { ", repaired" } else { "" })?;
+        }
+        for (path, error) in &self.errors {
+            writeln!(f, "{}: {error}", path.display())?;
+        }
+        let verb = if self.repair { "repaired" } else { "damaged" };
+        writeln!(
+            f,
+            "checked {} logs, {verb} {}, {} errors",
+            self.files,
+            self.damaged.len(),
+            self.errors.len()
+        )
+    }
+}
+
+/// Checks the logs of all targets in `log_dir` and, with `repair`, removes
+/// the corrupt lines.
+///
+/// The repair should not run concurrently with the monitors, as pings that
+/// are logged in the meantime might be lost.
+pub fn run(log_dir: &Path, repair: bool) -> Report {
+    let mut report = Report {
+        repair,
+        ..Report::default()
+    };
+    for dir in retention::target_dirs(log_dir) {
+        for name in ping_stats::log_files(&dir) {
+            let path = dir.join(name);
+            report.files += 1;
+            match check(&path) {
+                Ok(None) => {}
+                Ok(Some(damage)) => {
+                    if repair {
+                        if let Err(e) = fix(&damage) {
+                            report.errors.push((path, e));
+                            continue;
+                        }
+                    }
+                    report.damaged.push(damage);
+                }
+                Err(e) => report.errors.push((path, e)),
+            }
+        }
+    }
+    report
+}
+
+/// Returns the damage of the log file, `None` if it is intact
+pub fn check(path: &Path) -> io::Result<Option<Damage>> {
+    let mut damage = Damage {
+        path: path.to_owned(),
+        lines: 0,
+        corrupt: Vec::new(),
+        unterminated: false,
+    };
+    for line in ping_stats::lines(BufReader::new(File::open(path)?)) {
+        let line = line?;
+        damage.lines += 1;
+        if ping_stats::parse_line(&line).is_none() {
+            damage.corrupt.push(damage.lines);
+        }
+        damage.unterminated = !line.ends_with(b"\n");
+    }
+    Ok((!damage.corrupt.is_empty() || damage.unterminated).then_some(damage))
+}
+
+/// Rewrites the log file without the corrupt lines
+fn fix(damage: &Damage) -> io::Result<()> {
+    let input = BufReader::new(File::open(&damage.path)?);
+    let temp = damage.path.with_extension("tmp");
+    let mut output = BufWriter::new(File::create(&temp)?);
+    let mut corrupt = damage.corrupt.iter().peekable();
+    for (i, line) in ping_stats::lines(input).enumerate() {
+        let mut line = line?;
+        if corrupt.next_if_eq(&&(i + 1)).is_some() {
+            continue;
+        }
+        if !line.ends_with(b"\n") {
+            line.push(b'\n');
+        }
+        output.write_all(&line)?;
+    }
+    output
+        .into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()?;
+    fs::rename(temp, &damage.path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn repair() {
+        let dir = std::env::temp_dir().join(format!("ping-log-verify-{}", std::process::id()));
+        let target = dir.join("1.1.1.1");
+        fs::create_dir_all(&target).unwrap();
+        fs::write(target.join("260101.txt"), "100 1\n160 1\n").unwrap();
+        fs::write(target.join("260102.txt"), "220 1\n28\0\0\n340 1\n400 ").unwrap();
+        fs::write(target.join("260103.txt"), "460 1\n520 1").unwrap();
+
+        let report = run(&dir, false);
+        assert_eq!(report.files, 3);
+        assert!(report.errors.is_empty());
+        assert_eq!(
+            report.damaged,
+            [
+                Damage {
+                    path: target.join("260102.txt"),
+                    lines: 4,
+                    corrupt: vec![2, 4],
+                    unterminated: true,
+                },
+                Damage {
+                    path: target.join("260103.txt"),
+                    lines: 2,
+                    corrupt: vec![],
+                    unterminated: true,
+                },
+            ]
+        );
+        // Verifying changes nothing
+        assert_eq!(run(&dir, false).damaged.len(), 2);
+
+        let report = run(&dir, true);
+        assert_eq!(report.damaged.len(), 2);
+        assert_eq!(
+            fs::read_to_string(target.join("260102.txt")).unwrap(),
+            "220 1\n340 1\n"
+        );
+        assert_eq!(
+            fs::read_to_string(target.join("260103.txt")).unwrap(),
+            "460 1\n520 1\n"
+        );
+        assert!(run(&dir, false).damaged.is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,438 @@
+//! Webhook notifications of the alerts and outages.
+//!
+//! The notifications are queued in a file of the log directory and retried
+//! with increasing delays until the webhooks accept them, also across
+//! restarts.
+
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::{Map, Value};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tracing::{error, warn};
+
+use super::http;
+use super::stream::{Hub, Update};
+use super::target::Http;
+
+/// Name of the queue in the log directory
+pub const QUEUE_FILE: &str = "webhooks.txt";
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Delay of the first retry in seconds
+const MIN_BACKOFF: i64 = 10;
+const MAX_BACKOFF: i64 = 60 * 60;
+/// Notifications are dropped after this many failed attempts
+const MAX_ATTEMPTS: u32 = 20;
+
+/// Queued notification
+#[derive(Debug, Clone, PartialEq)]
+struct Delivery {
+    /// Number of failed attempts
+    attempts: u32,
+    /// Time of the next attempt
+    due: i64,
+    url: String,
+    payload: String,
+}
+
+impl fmt::Display for Delivery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Delivery {
+            attempts,
+            due,
+            url,
+            payload,
+        } = self;
+        write!(f, "{attempts} {due} {url} {payload}")
+    }
+}
+
+impl FromStr for Delivery {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut columns = s.splitn(4, ' ');
+        Ok(Delivery {
+            attempts: columns.next().ok_or(())?.parse().map_err(|_| ())?,
+            due: columns.next().ok_or(())?.parse().map_err(|_| ())?,
+            url: columns.next().ok_or(())?.into(),
+            payload: columns.next().ok_or(())?.into(),
+        })
+    }
+}
+
+/// Webhooks that are notified about the alerts
+pub struct Webhooks {
+    urls: Vec<Http>,
+    /// Payload with `{{field}}` placeholders, the fields as JSON object if
+    /// missing
+    template: Option<String>,
+    queue: PathBuf,
+}
+
+impl Webhooks {
+    pub fn new(urls: Vec<Http>, template: Option<String>, queue: PathBuf) -> Webhooks {
+        Webhooks {
+            urls,
+            template,
+            queue,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.urls.is_empty()
+    }
+
+    /// Returns the payload for the update, `None` if it is not notified
+    pub fn payload(&self, update: &Update) -> Option<String> {
+        let fields = fields(update)?;
+        let Some(template) = &self.template else {
+            return Some(Value::Object(fields).to_string());
+        };
+        let mut payload = template.clone();
+        for (name, value) in &fields {
+            let value = match value {
+                // Escaped for a JSON string in the template
+                Value::String(s) => {
+                    let quoted = Value::String(s.clone()).to_string();
+                    quoted[1..quoted.len() - 1].to_owned()
+                }
+                Value::Null => "null".into(),
+                value => value.to_string(),
+            };
+            payload = payload.replace(&format!("{{{{{name}}}}}"), &value);
+        }
+        // A line per notification in the queue
+        Some(payload.replace(['\r', '\n'], " "))
+    }
+
+    fn load(&self) -> Vec<Delivery> {
+        let Ok(input) = fs::read_to_string(&self.queue) else {
+            return Vec::new();
+        };
+        input.lines().filter_map(|l| l.parse().ok()).collect()
+    }
+
+    fn save(&self, queue: &[Delivery]) {
+        let output: String = queue.iter().map(|d| format!("{d}\n")).collect();
+        let temp = self.queue.with_extension("tmp");
+        if let Err(e) = fs::write(&temp, output).and_then(|_| fs::rename(&temp, &self.queue)) {
+            error!("could not save the webhook queue: {e}");
+        }
+    }
+
+    fn enqueue(&self, queue: &mut Vec<Delivery>, payload: String, now: i64) {
+        for url in &self.urls {
+            queue.push(Delivery {
+                attempts: 0,
+                due: now,
+                url: url.url.clone(),
+                payload: payload.clone(),
+            });
+        }
+        self.save(queue);
+    }
+
+    /// Sends the due notifications and returns the remaining ones
+    async fn deliver(&self, queue: Vec<Delivery>, now: i64) -> Vec<Delivery> {
+        let mut changed = false;
+        let mut remaining = Vec::with_capacity(queue.len());
+        for mut delivery in queue {
+            if delivery.due > now {
+                remaining.push(delivery);
+                continue;
+            }
+            changed = true;
+            let Ok(url) = delivery.url.parse::<Http>() else {
+                error!("invalid webhook {}", delivery.url);
+                continue;
+            };
+            match http::post(&url, &delivery.payload, TIMEOUT).await {
+                Ok(status) if (200..300).contains(&status) => continue,
+                // Retrying would not help
+                Ok(status) if (400..500).contains(&status) && status != 408 && status != 429 => {
+                    error!("webhook {} rejected the notification: {status}", url.url);
+                    continue;
+                }
+                Ok(status) => warn!("webhook {} failed: {status}", url.url),
+                Err(outcome) => warn!("webhook {} failed: {}", url.url, outcome.as_str()),
+            }
+            delivery.attempts += 1;
+            if delivery.attempts >= MAX_ATTEMPTS {
+                error!(
+                    "dropped notification for {} after {MAX_ATTEMPTS} attempts",
+                    url.url
+                );
+                continue;
+            }
+            delivery.due = now + backoff(delivery.attempts);
+            remaining.push(delivery);
+        }
+        if changed {
+            self.save(&remaining);
+        }
+        remaining
+    }
+}
+
+/// Delay after the given number of failed attempts in seconds
+fn backoff(attempts: u32) -> i64 {
+    (MIN_BACKOFF << attempts.saturating_sub(1).min(16)).min(MAX_BACKOFF)
+}
+
+/// Returns the fields of the notification for the update
+fn fields(update: &Update) -> Option<Map<String, Value>> {
+    match update {
+        Update::Alert(alert) => {
+            let Ok(Value::Object(mut fields)) = serde_json::to_value(alert) else {
+                return None;
+            };
+            fields.insert("event".into(), "alert".into());
+            fields.insert("message".into(), alert.to_string().into());
+            fields.entry("until").or_insert(Value::Null);
+            Some(fields)
+        }
+        Update::Outage(outage) => {
+            let Ok(Value::Object(mut fields)) = serde_json::to_value(outage) else {
+                return None;
+            };
+            fields.insert("event".into(), "outage".into());
+            fields.insert("message".into(), outage.to_string().into());
+            Some(fields)
+        }
+        _ => None,
+    }
+}
+
+/// Queues the notifications of the updates and delivers them
+///
+/// The delivery runs in a separate task, so slow webhooks do not keep the
+/// updates from being received.
+pub async fn run(webhooks: Arc<Webhooks>, hub: Arc<Hub>) {
+    let mut receiver = hub.receiver();
+    let (sender, payloads) = mpsc::unbounded_channel();
+    tokio::spawn(send_queued(webhooks.clone(), payloads));
+    loop {
+        let update = match receiver.recv().await {
+            Ok(update) => update,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("webhooks skipped {skipped} updates");
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        if let Some(payload) = webhooks.payload(&update) {
+            if sender.send(payload).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Queues the payloads and delivers the notifications until they succeed
+async fn send_queued(webhooks: Arc<Webhooks>, mut payloads: mpsc::UnboundedReceiver<String>) {
+    let mut queue = webhooks.load();
+    loop {
+        let now = chrono::Local::now().timestamp();
+        // Payloads that arrived during the last delivery
+        while let Ok(payload) = payloads.try_recv() {
+            webhooks.enqueue(&mut queue, payload, now);
+        }
+        queue = webhooks.deliver(queue, now).await;
+
+        let wait = queue
+            .iter()
+            .map(|d| d.due - now)
+            .min()
+            .unwrap_or(MAX_BACKOFF);
+        match tokio::time::timeout(secs(wait), payloads.recv()).await {
+            Ok(Some(payload)) => {
+                let now = chrono::Local::now().timestamp();
+                webhooks.enqueue(&mut queue, payload, now);
+            }
+            Ok(None) => return,
+            // Retry the due notifications
+            Err(_) => {}
+        }
+    }
+}
+
+fn secs(secs: i64) -> Duration {
+    Duration::from_secs(secs.max(1) as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use axum::http::StatusCode;
+    use axum::routing::post;
+
+    use crate::alert::{Alert, State};
+    use crate::outage::Outage;
+
+    fn alert() -> Update {
+        Update::Alert(Alert {
+            rule: "loss>5,2/600s".into(),
+            subject: "1.1.1.1".into(),
+            state: State::Firing,
+            value: 12.5,
+            since: 100,
+            until: None,
+        })
+    }
+
+    fn queue_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "ping-log-webhook-{name}-{}.txt",
+            std::process::id()
+        ))
+    }
+
+    #[test]
+    fn payloads() {
+        let webhooks = Webhooks::new(Vec::new(), None, queue_path("payloads"));
+        let payload: Value = serde_json::from_str(&webhooks.payload(&alert()).unwrap()).unwrap();
+        assert_eq!(payload["event"], "alert");
+        assert_eq!(payload["state"], "firing");
+        assert_eq!(payload["value"], 12.5);
+        assert_eq!(payload["until"], Value::Null);
+        assert!(webhooks.payload(&Update::Mc(Vec::new())).is_none());
+
+        let outage = Update::Outage(Outage {
+            target: "1.1.1.1".into(),
+            start: 100,
+            end: Some(400),
+            duration: 300,
+            lost: 5,
+        });
+        let payload: Value = serde_json::from_str(&webhooks.payload(&outage).unwrap()).unwrap();
+        assert_eq!(payload["event"], "outage");
+        assert_eq!(
+            payload["message"],
+            "outage of 1.1.1.1 ended after 300s (5 lost)"
+        );
+
+        let template = "{\"text\": \"{{message}} \\\"{{subject}}\\\"\",\n \"since\": {{since}}}";
+        let webhooks = Webhooks::new(Vec::new(), Some(template.into()), queue_path("payloads"));
+        let payload = webhooks.payload(&alert()).unwrap();
+        assert!(!payload.contains('\n'));
+        let payload: Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(
+            payload["text"],
+            "loss>5,2/600s of 1.1.1.1 firing (12.5) \"1.1.1.1\""
+        );
+        assert_eq!(payload["since"], 100);
+    }
+
+    #[test]
+    fn backoffs() {
+        assert_eq!(backoff(1), MIN_BACKOFF);
+        assert_eq!(backoff(2), 2 * MIN_BACKOFF);
+        assert_eq!(backoff(MAX_ATTEMPTS), MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn delivery() {
+        // Fails the first request
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let app = axum::Router::new().route(
+            "/hook",
+            post({
+                let received = received.clone();
+                move |body: String| async move {
+                    let mut received = received.lock().unwrap();
+                    received.push(body);
+                    if received.len() == 1 {
+                        StatusCode::SERVICE_UNAVAILABLE
+                    } else {
+                        StatusCode::NO_CONTENT
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let path = queue_path("delivery");
+        let url = format!("http://{addr}/hook").parse().unwrap();
+        let webhooks = Webhooks::new(vec![url], None, path.clone());
+        let mut queue = Vec::new();
+        let payload = webhooks.payload(&alert()).unwrap();
+        webhooks.enqueue(&mut queue, payload.clone(), 1000);
+
+        let queue = webhooks.deliver(queue, 1000).await;
+        assert_eq!(queue.len(), 1);
+        assert_eq!((queue[0].attempts, queue[0].due), (1, 1000 + MIN_BACKOFF));
+        // Persisted for restarts
+        let queue = webhooks.load();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].payload, payload);
+
+        // Not due yet
+        let queue = webhooks.deliver(queue, 1001).await;
+        assert_eq!(received.lock().unwrap().len(), 1);
+        let queue = webhooks.deliver(queue, 1000 + MIN_BACKOFF).await;
+        assert!(queue.is_empty());
+        assert!(webhooks.load().is_empty());
+        assert_eq!(*received.lock().unwrap(), [payload.clone(), payload]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn slow_webhook() {
+        // Delays the first request
+        let received = Arc::new(Mutex::new(0));
+        let app = axum::Router::new().route(
+            "/hook",
+            post({
+                let received = received.clone();
+                move || async move {
+                    let first = {
+                        let mut received = received.lock().unwrap();
+                        *received += 1;
+                        *received == 1
+                    };
+                    if first {
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                    StatusCode::NO_CONTENT
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let path = queue_path("slow");
+        let url = format!("http://{addr}/hook").parse().unwrap();
+        let webhooks = Arc::new(Webhooks::new(vec![url], None, path.clone()));
+        let hub = Arc::new(Hub::new(2));
+        tokio::spawn(run(webhooks, hub.clone()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // More updates than the hub buffers while the first one is posted
+        for _ in 0..5 {
+            hub.publish(alert());
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        for _ in 0..50 {
+            if *received.lock().unwrap() == 5 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(*received.lock().unwrap(), 5);
+
+        let _ = fs::remove_file(path);
+    }
+}